@@ -0,0 +1,428 @@
+use crate::digest::Digest;
+use crate::error::HasherError;
+use hmac::digest::core_api::BlockSizeUser;
+use hmac::{Mac, SimpleHmac};
+use sha2::{Sha256, Sha384, Sha512, Sha512_256};
+use sha3::Sha3_256;
+use std::fmt;
+use std::io::{self, Read};
+use std::str::FromStr;
+use subtle::ConstantTimeEq;
+
+/// Digest algorithms supported by the hasher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+    Sha512_256,
+    Sha3_256,
+    Blake3,
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 6] = [
+        Algorithm::Sha256,
+        Algorithm::Sha384,
+        Algorithm::Sha512,
+        Algorithm::Sha512_256,
+        Algorithm::Sha3_256,
+        Algorithm::Blake3,
+    ];
+
+    /// The canonical (`hashlib`-style) name of the algorithm.
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha384 => "sha384",
+            Algorithm::Sha512 => "sha512",
+            Algorithm::Sha512_256 => "sha512_256",
+            Algorithm::Sha3_256 => "sha3_256",
+            Algorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// Length of the digest in bytes.
+    pub fn output_size(self) -> usize {
+        match self {
+            Algorithm::Sha256 => <Sha256 as sha2::Digest>::output_size(),
+            Algorithm::Sha384 => <Sha384 as sha2::Digest>::output_size(),
+            Algorithm::Sha512 => <Sha512 as sha2::Digest>::output_size(),
+            Algorithm::Sha512_256 => <Sha512_256 as sha2::Digest>::output_size(),
+            Algorithm::Sha3_256 => <Sha3_256 as sha2::Digest>::output_size(),
+            Algorithm::Blake3 => blake3::OUT_LEN,
+        }
+    }
+
+    /// Calculates the digest of `data`.
+    pub fn digest(self, data: &[u8]) -> Digest {
+        let bytes = match self {
+            Algorithm::Sha256 => <Sha256 as sha2::Digest>::digest(data).to_vec(),
+            Algorithm::Sha384 => <Sha384 as sha2::Digest>::digest(data).to_vec(),
+            Algorithm::Sha512 => <Sha512 as sha2::Digest>::digest(data).to_vec(),
+            Algorithm::Sha512_256 => <Sha512_256 as sha2::Digest>::digest(data).to_vec(),
+            Algorithm::Sha3_256 => <Sha3_256 as sha2::Digest>::digest(data).to_vec(),
+            Algorithm::Blake3 => blake3::hash(data).as_bytes().to_vec(),
+        };
+        Digest::new(self, bytes)
+    }
+
+    /// Calculates the digest of everything read from `reader`, without buffering it all in memory.
+    pub fn digest_reader(self, reader: impl Read) -> io::Result<Digest> {
+        let bytes = match self {
+            Algorithm::Sha256 => digest_reader_with::<Sha256>(reader)?,
+            Algorithm::Sha384 => digest_reader_with::<Sha384>(reader)?,
+            Algorithm::Sha512 => digest_reader_with::<Sha512>(reader)?,
+            Algorithm::Sha512_256 => digest_reader_with::<Sha512_256>(reader)?,
+            Algorithm::Sha3_256 => digest_reader_with::<Sha3_256>(reader)?,
+            Algorithm::Blake3 => {
+                let mut hasher = blake3::Hasher::new();
+                hasher.update_reader(reader)?;
+                hasher.finalize().as_bytes().to_vec()
+            }
+        };
+        Ok(Digest::new(self, bytes))
+    }
+
+    /// Calculates the HMAC tag of `message` under `key` (RFC 2104).
+    pub fn hmac(self, key: &[u8], message: &[u8]) -> Result<Digest, HasherError> {
+        let bytes = match self {
+            Algorithm::Sha256 => hmac_with::<Sha256>(key, message)?,
+            Algorithm::Sha384 => hmac_with::<Sha384>(key, message)?,
+            Algorithm::Sha512 => hmac_with::<Sha512>(key, message)?,
+            Algorithm::Sha512_256 => hmac_with::<Sha512_256>(key, message)?,
+            Algorithm::Sha3_256 => hmac_with::<Sha3_256>(key, message)?,
+            Algorithm::Blake3 => hmac_blake3(key, message).to_vec(),
+        };
+        Ok(Digest::new(self, bytes))
+    }
+
+    /// Verifies an HMAC tag of `message` under `key` in constant time.
+    /// A tag of the wrong length for this algorithm is reported as malformed rather than just invalid.
+    pub fn verify_hmac(self, key: &[u8], message: &[u8], tag: &[u8]) -> Result<bool, HasherError> {
+        if tag.len() != self.output_size() {
+            return Err(HasherError::InvalidDigest(format!(
+                "Malformed HMAC tag: expected {} bytes, got {}",
+                self.output_size(),
+                tag.len()
+            )));
+        }
+        Ok(match self {
+            Algorithm::Sha256 => verify_hmac_with::<Sha256>(key, message, tag)?,
+            Algorithm::Sha384 => verify_hmac_with::<Sha384>(key, message, tag)?,
+            Algorithm::Sha512 => verify_hmac_with::<Sha512>(key, message, tag)?,
+            Algorithm::Sha512_256 => verify_hmac_with::<Sha512_256>(key, message, tag)?,
+            Algorithm::Sha3_256 => verify_hmac_with::<Sha3_256>(key, message, tag)?,
+            Algorithm::Blake3 => hmac_blake3(key, message).ct_eq(tag).into(),
+        })
+    }
+}
+
+fn digest_reader_with<D: sha2::Digest + io::Write>(mut reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut hasher = D::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hasher.finalize().to_vec())
+}
+
+fn hmac_with<D: sha2::Digest + BlockSizeUser>(
+    key: &[u8],
+    message: &[u8],
+) -> Result<Vec<u8>, hmac::digest::InvalidLength> {
+    let mut mac = <SimpleHmac<D> as Mac>::new_from_slice(key)?;
+    mac.update(message);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// Checks the tag with `Mac::verify_slice`, which compares in constant time.
+fn verify_hmac_with<D: sha2::Digest + BlockSizeUser>(
+    key: &[u8],
+    message: &[u8],
+    tag: &[u8],
+) -> Result<bool, hmac::digest::InvalidLength> {
+    let mut mac = <SimpleHmac<D> as Mac>::new_from_slice(key)?;
+    mac.update(message);
+    Ok(mac.verify_slice(tag).is_ok())
+}
+
+/// HMAC over BLAKE3 with its 64-byte block size. The `blake3` crate only exposes the
+/// RustCrypto traits behind an unstable feature, so the construction is spelled out here.
+fn hmac_blake3(key: &[u8], message: &[u8]) -> [u8; blake3::OUT_LEN] {
+    const BLOCK_SIZE: usize = 64;
+    let mut block_key = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block_key[..blake3::OUT_LEN].copy_from_slice(blake3::hash(key).as_bytes());
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = blake3::Hasher::new();
+    inner.update(&block_key.map(|b| b ^ 0x36));
+    inner.update(message);
+    let mut outer = blake3::Hasher::new();
+    outer.update(&block_key.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize().as_bytes());
+    *outer.finalize().as_bytes()
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Error returned when parsing an unknown algorithm name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownAlgorithm(pub String);
+
+impl fmt::Display for UnknownAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let supported: Vec<&str> = Algorithm::ALL.iter().map(|a| a.name()).collect();
+        write!(
+            f,
+            "Unsupported algorithm '{}'; expected one of: {}",
+            self.0,
+            supported.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownAlgorithm {}
+
+impl FromStr for Algorithm {
+    type Err = UnknownAlgorithm;
+
+    /// Parses an algorithm name case-insensitively, accepting `-` as well as `_` (e.g. `SHA3-256`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.trim().to_ascii_lowercase().replace('-', "_");
+        Algorithm::ALL
+            .into_iter()
+            .find(|a| a.name() == normalized)
+            .ok_or_else(|| UnknownAlgorithm(s.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// FIPS 180-4 / FIPS 202 "abc" examples and the BLAKE3 reference implementation.
+    const ABC_DIGESTS: [(Algorithm, &str); 5] = [
+        (
+            Algorithm::Sha384,
+            "cb00753f45a35e8bb5a03d699ac65007272c32ab0eded1631a8b605a43ff5bed8086072ba1e7cc2358baeca134c825a7",
+        ),
+        (
+            Algorithm::Sha512,
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+        ),
+        (
+            Algorithm::Sha512_256,
+            "53048e2681941ef99b2e29b76b4c7dabe4c2d0c634fc6d46e0e2f13107e7af23",
+        ),
+        (
+            Algorithm::Sha3_256,
+            "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532",
+        ),
+        (
+            Algorithm::Blake3,
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+        ),
+    ];
+
+    const EMPTY_DIGESTS: [(Algorithm, &str); 5] = [
+        (
+            Algorithm::Sha384,
+            "38b060a751ac96384cd9327eb1b1e36a21fdb71114be07434c0cc7bf63f6e1da274edebfe76f65fbd51ad2f14898b95b",
+        ),
+        (
+            Algorithm::Sha512,
+            "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e",
+        ),
+        (
+            Algorithm::Sha512_256,
+            "c672b8d1ef56ed28ab87c3622c5114069bdd3ad7b8f9737498d0c01ecef0967a",
+        ),
+        (
+            Algorithm::Sha3_256,
+            "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a",
+        ),
+        (
+            Algorithm::Blake3,
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+        ),
+    ];
+
+    /// RFC 4231 test case 2.
+    const SHORT_KEY: &[u8] = b"Jefe";
+    const SHORT_KEY_MESSAGE: &[u8] = b"what do ya want for nothing?";
+    /// RFC 4231 test case 6: a 131-byte key, longer than every block size here.
+    const LONG_KEY: [u8; 131] = [0xaa; 131];
+    const LONG_KEY_MESSAGE: &[u8] = b"Test Using Larger Than Block-Size Key - Hash Key First";
+
+    /// Tags for (test case 2, test case 6). SHA-384 and SHA-512 are from RFC 4231; the others
+    /// use the same inputs and were produced with Python's `hmac` and, for BLAKE3, RustCrypto's
+    /// `SimpleHmac` over `blake3::Hasher`.
+    const HMAC_TAGS: [(Algorithm, &str, &str); 5] = [
+        (
+            Algorithm::Sha384,
+            "af45d2e376484031617f78d2b58a6b1b9c7ef464f5a01b47e42ec3736322445e8e2240ca5e69e2c78b3239ecfab21649",
+            "4ece084485813e9088d2c63a041bc5b44f9ef1012a2b588f3cd11f05033ac4c60c2ef6ab4030fe8296248df163f44952",
+        ),
+        (
+            Algorithm::Sha512,
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737",
+            "80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f3526b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598",
+        ),
+        (
+            Algorithm::Sha512_256,
+            "6df7b24630d5ccb2ee335407081a87188c221489768fa2020513b2d593359456",
+            "87123c45f7c537a404f8f47cdbedda1fc9bec60eeb971982ce7ef10e774e6539",
+        ),
+        (
+            Algorithm::Sha3_256,
+            "c7d4072e788877ae3596bbb0da73b887c9171f93095b294ae857fbe2645e1ba5",
+            "ed73a374b96c005235f948032f09674a58c0ce555cfc1f223b02356560312c3b",
+        ),
+        (
+            Algorithm::Blake3,
+            "732da99ccc24e277b2fec6c42e0f29f1093689ff0821de4df22f7faec5168776",
+            "206553225c4716b9b4f6fc279d4d67d5a033e3b6520f2c0aad2d6f91ff06762a",
+        ),
+    ];
+
+    #[test]
+    fn digest_known_answers() {
+        for (algorithm, expected) in ABC_DIGESTS {
+            assert_eq!(algorithm.digest(b"abc").to_hex(), expected, "{}", algorithm);
+        }
+        for (algorithm, expected) in EMPTY_DIGESTS {
+            assert_eq!(algorithm.digest(b"").to_hex(), expected, "{}", algorithm);
+        }
+    }
+
+    #[test]
+    fn digests_have_the_advertised_size() {
+        for algorithm in Algorithm::ALL {
+            let digest = algorithm.digest(b"abc");
+            assert_eq!(digest.algorithm(), algorithm);
+            assert_eq!(
+                digest.as_bytes().len(),
+                algorithm.output_size(),
+                "{}",
+                algorithm
+            );
+        }
+    }
+
+    #[test]
+    fn digest_reader_matches_digest() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 253) as u8).collect();
+        for algorithm in Algorithm::ALL {
+            assert_eq!(
+                algorithm.digest_reader(data.as_slice()).unwrap(),
+                algorithm.digest(&data),
+                "{}",
+                algorithm
+            );
+            assert_eq!(
+                algorithm.digest_reader(io::empty()).unwrap(),
+                algorithm.digest(b""),
+                "{}",
+                algorithm
+            );
+        }
+    }
+
+    #[test]
+    fn hmac_known_answers() {
+        for (algorithm, short_key_tag, long_key_tag) in HMAC_TAGS {
+            assert_eq!(
+                algorithm
+                    .hmac(SHORT_KEY, SHORT_KEY_MESSAGE)
+                    .unwrap()
+                    .to_hex(),
+                short_key_tag,
+                "{}",
+                algorithm
+            );
+            assert_eq!(
+                algorithm
+                    .hmac(&LONG_KEY, LONG_KEY_MESSAGE)
+                    .unwrap()
+                    .to_hex(),
+                long_key_tag,
+                "{}",
+                algorithm
+            );
+        }
+    }
+
+    #[test]
+    fn hmac_blake3_known_answers() {
+        assert_eq!(
+            hex::encode(hmac_blake3(SHORT_KEY, SHORT_KEY_MESSAGE)),
+            "732da99ccc24e277b2fec6c42e0f29f1093689ff0821de4df22f7faec5168776"
+        );
+        assert_eq!(
+            hex::encode(hmac_blake3(&LONG_KEY, LONG_KEY_MESSAGE)),
+            "206553225c4716b9b4f6fc279d4d67d5a033e3b6520f2c0aad2d6f91ff06762a"
+        );
+        // A key longer than the block size is hashed first, so the two are interchangeable.
+        assert_eq!(
+            hmac_blake3(&LONG_KEY, LONG_KEY_MESSAGE),
+            hmac_blake3(blake3::hash(&LONG_KEY).as_bytes(), LONG_KEY_MESSAGE)
+        );
+    }
+
+    #[test]
+    fn verify_hmac_accepts_only_the_right_tag() {
+        for algorithm in Algorithm::ALL {
+            let tag = algorithm.hmac(SHORT_KEY, SHORT_KEY_MESSAGE).unwrap();
+            assert!(
+                algorithm
+                    .verify_hmac(SHORT_KEY, SHORT_KEY_MESSAGE, tag.as_bytes())
+                    .unwrap()
+            );
+            assert!(
+                !algorithm
+                    .verify_hmac(b"other", SHORT_KEY_MESSAGE, tag.as_bytes())
+                    .unwrap()
+            );
+
+            let mut tampered = tag.into_bytes();
+            tampered[0] ^= 1;
+            assert!(
+                !algorithm
+                    .verify_hmac(SHORT_KEY, SHORT_KEY_MESSAGE, &tampered)
+                    .unwrap()
+            );
+            assert!(matches!(
+                algorithm.verify_hmac(SHORT_KEY, SHORT_KEY_MESSAGE, &tampered[1..]),
+                Err(HasherError::InvalidDigest(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn from_str_normalizes_case_and_separators() {
+        for algorithm in Algorithm::ALL {
+            assert_eq!(algorithm.name().parse(), Ok(algorithm));
+            assert_eq!(algorithm.name().to_uppercase().parse(), Ok(algorithm));
+        }
+        assert_eq!("SHA3-256".parse(), Ok(Algorithm::Sha3_256));
+        assert_eq!("sha512-256".parse(), Ok(Algorithm::Sha512_256));
+        assert_eq!(" Blake3 ".parse(), Ok(Algorithm::Blake3));
+    }
+
+    #[test]
+    fn from_str_rejects_unknown_names() {
+        for name in ["md5", "sha1", "sha-256", "sha3256", ""] {
+            assert_eq!(
+                name.parse::<Algorithm>(),
+                Err(UnknownAlgorithm(name.to_string()))
+            );
+        }
+        let message = "md5".parse::<Algorithm>().unwrap_err().to_string();
+        assert!(message.contains("'md5'"));
+        assert!(message.contains("sha3_256"));
+    }
+}
@@ -0,0 +1,678 @@
+//! `hashsure`: hash, tag and verify documents from the command line, without the FastAPI server.
+//!
+//! Exit codes: 0 when everything matched, 1 when a digest or tag did not match, 2 on any error.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use document_hasher_rust::checksums::{self, CheckStatus, ManifestEntry, ManifestStyle};
+use document_hasher_rust::key::{DEFAULT_MIN_KEY_LENGTH, HmacKey};
+use document_hasher_rust::record::Record;
+use document_hasher_rust::{Algorithm, Digest, HasherError};
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[cfg(test)]
+#[path = "../test_support.rs"]
+mod test_support;
+
+/// Environment variable the HMAC key is read from when `--key-file` is not given.
+const DEFAULT_KEY_ENV: &str = "HASHSURE_HMAC_KEY";
+
+const EXIT_MISMATCH: u8 = 1;
+const EXIT_ERROR: u8 = 2;
+
+#[derive(Parser)]
+#[command(
+    name = "hashsure",
+    version,
+    about = "Hash, tag and verify documents for HashSure"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the digest of each file (or stdin).
+    Hash {
+        #[command(flatten)]
+        input: InputArgs,
+        #[arg(short, long, default_value = "sha256", value_parser = parse_algorithm)]
+        algorithm: Algorithm,
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Hex)]
+        format: OutputFormat,
+        /// Print BSD tagged lines (`SHA256 (<file>) = <hex>`), like `sha256sum --tag`.
+        #[arg(long, conflicts_with = "format")]
+        tag: bool,
+    },
+    /// Print the registration HMAC tag of a document, as computed by the server: HMAC-SHA256 over the
+    /// canonical record of its SHA256 digest, file name and upload time.
+    Hmac {
+        /// The document, or `-` for stdin.
+        file: PathBuf,
+        #[command(flatten)]
+        record: RecordArgs,
+        #[command(flatten)]
+        key: KeyArgs,
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Hex)]
+        format: OutputFormat,
+    },
+    /// Verify one document against an expected SHA256 digest and/or registration HMAC tag.
+    Verify {
+        /// The document to verify, or `-` for stdin.
+        file: PathBuf,
+        /// Expected SHA256 digest (hex).
+        #[arg(long, required_unless_present = "tag")]
+        digest: Option<String>,
+        /// Expected registration HMAC tag (hex); requires a key and the registration's upload time.
+        #[arg(long)]
+        tag: Option<String>,
+        #[command(flatten)]
+        record: RecordArgs,
+        #[command(flatten)]
+        key: KeyArgs,
+    },
+    /// Check documents against a checksum list, like `sha256sum -c`.
+    /// Accepts GNU (`<hex>  <file>`) and BSD tagged (`SHA256 (<file>) = <hex>`) lines.
+    Check {
+        /// The checksum list, or `-` for stdin.
+        list: PathBuf,
+        /// Algorithm of untagged (GNU) lines; BSD lines name their own.
+        #[arg(short, long, default_value = "sha256", value_parser = parse_algorithm)]
+        algorithm: Algorithm,
+        /// Don't print OK for each successfully verified file.
+        #[arg(short, long)]
+        quiet: bool,
+    },
+}
+
+#[derive(Args)]
+struct InputArgs {
+    /// Files to read; `-` or no files reads stdin.
+    files: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct KeyArgs {
+    /// Read the HMAC key from this file (a single trailing newline is ignored).
+    #[arg(long, conflicts_with = "key_env")]
+    key_file: Option<PathBuf>,
+    /// Read the HMAC key from this environment variable [default: HASHSURE_HMAC_KEY].
+    #[arg(long)]
+    key_env: Option<String>,
+}
+
+/// The stored fields a registration tag covers besides the digest (see `document_hasher_rust::record`).
+#[derive(Args)]
+struct RecordArgs {
+    /// File name stored with the registration [default: the document's file name].
+    #[arg(long)]
+    file_name: Option<String>,
+    /// Upload time stored with the registration: `YYYY-MM-DD HH:MM:SS[.ffffff]`, as the server stores it, in UTC
+    /// unless followed by `Z` or a `+HH:MM`/`-HH:MM` offset; or microseconds since the Unix epoch.
+    #[arg(long, value_parser = parse_uploaded_at)]
+    uploaded_at: Option<i64>,
+    /// Use the legacy tag over the hex SHA256 alone, which rows stored with `record_version` 0 carry.
+    #[arg(long, conflicts_with_all = ["file_name", "uploaded_at"])]
+    legacy: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Hex,
+    Base64,
+    Json,
+}
+
+/// Why a command stopped: a verification mismatch is reported separately from an error.
+enum Failure {
+    Mismatch,
+    Error(String),
+}
+
+impl From<HasherError> for Failure {
+    fn from(e: HasherError) -> Self {
+        Failure::Error(e.to_string())
+    }
+}
+
+fn parse_algorithm(name: &str) -> Result<Algorithm, String> {
+    name.parse()
+        .map_err(|e: document_hasher_rust::algorithm::UnknownAlgorithm| e.to_string())
+}
+
+/// Parses an upload time given as microseconds since the Unix epoch or as a date and time, which is UTC
+/// unless it carries an offset, and returns microseconds since the Unix epoch.
+fn parse_uploaded_at(value: &str) -> Result<i64, String> {
+    if let Ok(micros) = value.parse() {
+        return Ok(micros);
+    }
+    let invalid = || {
+        format!(
+            "invalid upload time '{}': expected YYYY-MM-DD HH:MM:SS[.ffffff][Z|+HH:MM] or microseconds",
+            value
+        )
+    };
+    let (date, time) = value
+        .strip_suffix('Z')
+        .unwrap_or(value)
+        .split_once(['T', ' '])
+        .ok_or_else(invalid)?;
+    let (time, offset_seconds) = match time.find(['+', '-']) {
+        Some(sign_at) => {
+            let (time, offset) = time.split_at(sign_at);
+            (time, parse_utc_offset(offset).ok_or_else(invalid)?)
+        }
+        None => (time, 0),
+    };
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let numbers = |text: &str, separator: char| -> Option<Vec<u16>> {
+        text.split(separator).map(|n| n.parse().ok()).collect()
+    };
+    let (Some([year, month, day]), Some([hour, minute, second])) = (
+        numbers(date, '-').and_then(|n| <[u16; 3]>::try_from(n).ok()),
+        numbers(time, ':').and_then(|n| <[u16; 3]>::try_from(n).ok()),
+    ) else {
+        return Err(invalid());
+    };
+    if fraction.len() > 6 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let micros: i64 = format!("{:0<6}", fraction).parse().map_err(|_| invalid())?;
+    let narrow = |n: u16| u8::try_from(n).map_err(|_| invalid());
+    let datetime = der::DateTime::new(
+        year,
+        narrow(month)?,
+        narrow(day)?,
+        narrow(hour)?,
+        narrow(minute)?,
+        narrow(second)?,
+    )
+    .map_err(|_| invalid())?;
+    Ok((datetime.unix_duration().as_secs() as i64 - offset_seconds) * 1_000_000 + micros)
+}
+
+/// Parses a `+HH:MM` or `-HH:MM` UTC offset into seconds east of UTC.
+fn parse_utc_offset(offset: &str) -> Option<i64> {
+    let (sign, offset) = match offset.split_at_checked(1)? {
+        ("+", offset) => (1, offset),
+        ("-", offset) => (-1, offset),
+        _ => return None,
+    };
+    let (hours, minutes) = offset.split_once(':')?;
+    if hours.len() != 2 || minutes.len() != 2 {
+        return None;
+    }
+    let (hours, minutes): (i64, i64) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours < 24 && minutes < 60).then_some(sign * (hours * 3600 + minutes * 60))
+}
+
+fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+fn open_input(path: &Path) -> Result<Box<dyn Read>, HasherError> {
+    if is_stdin(path) {
+        Ok(Box::new(io::stdin().lock()))
+    } else {
+        let file = File::open(path).map_err(|e| HasherError::io(path, e))?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+fn digest_input(algorithm: Algorithm, path: &Path) -> Result<Digest, HasherError> {
+    let reader = open_input(path)?;
+    algorithm
+        .digest_reader(reader)
+        .map_err(|e| HasherError::io(path, e))
+}
+
+fn input_files(input: &InputArgs) -> Vec<PathBuf> {
+    if input.files.is_empty() {
+        vec![PathBuf::from("-")]
+    } else {
+        input.files.clone()
+    }
+}
+
+fn read_key(args: &KeyArgs) -> Result<HmacKey, Failure> {
+    let bytes = match (&args.key_file, &args.key_env) {
+        (Some(path), _) => {
+            let mut bytes = std::fs::read(path).map_err(|e| HasherError::io(path, e))?;
+            if bytes.ends_with(b"\n") {
+                bytes.pop();
+                if bytes.ends_with(b"\r") {
+                    bytes.pop();
+                }
+            }
+            bytes
+        }
+        (None, env) => {
+            let name = env.as_deref().unwrap_or(DEFAULT_KEY_ENV);
+            let value = std::env::var_os(name).ok_or_else(|| {
+                Failure::Error(format!(
+                    "HMAC key not set: environment variable {} is unset",
+                    name
+                ))
+            })?;
+            if value.is_empty() {
+                return Err(Failure::Error(format!(
+                    "HMAC key not set: environment variable {} is empty",
+                    name
+                )));
+            }
+            value.into_encoded_bytes()
+        }
+    };
+    Ok(HmacKey::new(&bytes, DEFAULT_MIN_KEY_LENGTH, None)?)
+}
+
+/// Prints one result; in JSON the value is reported under `field` (`digest` or `hmac_tag`).
+fn print_digest(format: OutputFormat, field: &str, path: &Path, digest: &Digest) {
+    let name = path.display();
+    match format {
+        OutputFormat::Hex => println!("{}  {}", digest.to_hex(), name),
+        OutputFormat::Base64 => println!("{}  {}", BASE64.encode(digest.as_bytes()), name),
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::json!({ "file": name.to_string(), "algorithm": digest.algorithm().name(), field: digest.to_hex() })
+        ),
+    }
+}
+
+/// Hashes every input, like `sha256sum`: a file that cannot be read is reported and skipped, and the command
+/// fails once all the others have been hashed.
+fn run_hash(
+    input: &InputArgs,
+    algorithm: Algorithm,
+    format: OutputFormat,
+    tag: bool,
+) -> Result<(), Failure> {
+    let mut unreadable = 0;
+    for path in input_files(input) {
+        let digest = match digest_input(algorithm, &path) {
+            Ok(digest) => digest,
+            Err(e) => {
+                eprintln!("hashsure: {}", e);
+                unreadable += 1;
+                continue;
+            }
+        };
+        if tag {
+            let entry = ManifestEntry {
+                path: path.display().to_string(),
+                digest,
+                binary: false,
+            };
+            println!("{}", checksums::format_line(&entry, ManifestStyle::Bsd));
+        } else {
+            print_digest(format, "digest", &path, &digest);
+        }
+    }
+    if unreadable > 0 {
+        return Err(Failure::Error(format!(
+            "{} file(s) could not be read",
+            unreadable
+        )));
+    }
+    Ok(())
+}
+
+/// The message the server's registration tag covers for a document with SHA256 `digest`: the canonical
+/// record, or the hex digest alone for a legacy tag.
+fn registration_message(
+    path: &Path,
+    digest: Digest,
+    args: &RecordArgs,
+) -> Result<Vec<u8>, Failure> {
+    if args.legacy {
+        return Ok(digest.to_hex().into_bytes());
+    }
+    let uploaded_at = args.uploaded_at.ok_or_else(|| {
+        Failure::Error(
+            "--uploaded-at is required for a registration tag (or pass --legacy)".to_string(),
+        )
+    })?;
+    let file_name = match (&args.file_name, path.file_name()) {
+        (Some(file_name), _) => file_name.clone(),
+        (None, Some(file_name)) if !is_stdin(path) => file_name.to_string_lossy().into_owned(),
+        (None, _) => {
+            return Err(Failure::Error(
+                "--file-name is required when reading stdin".to_string(),
+            ));
+        }
+    };
+    Ok(Record::new(digest, &file_name, uploaded_at).canonical_bytes())
+}
+
+fn run_hmac(
+    file: &Path,
+    record: &RecordArgs,
+    key: &KeyArgs,
+    format: OutputFormat,
+) -> Result<(), Failure> {
+    let key = read_key(key)?;
+    let message = registration_message(file, digest_input(Algorithm::Sha256, file)?, record)?;
+    let tag = document_hasher_rust::hmac_sha256(key.as_bytes(), &message)?;
+    print_digest(format, "hmac_tag", file, &tag);
+    Ok(())
+}
+
+fn run_verify(
+    file: &Path,
+    digest: Option<&str>,
+    tag: Option<&str>,
+    record: &RecordArgs,
+    key: &KeyArgs,
+) -> Result<(), Failure> {
+    let actual = digest_input(Algorithm::Sha256, file)?;
+    let mut matched = true;
+    if let Some(expected) = digest {
+        let expected = Digest::from_hex(Algorithm::Sha256, expected.trim())?;
+        let ok = expected == actual;
+        println!(
+            "{}: digest {}",
+            file.display(),
+            if ok { "OK" } else { "FAILED" }
+        );
+        matched &= ok;
+    }
+    if let Some(expected) = tag {
+        let key = read_key(key)?;
+        let expected = hex::decode(expected.trim())
+            .map_err(|e| HasherError::InvalidDigest(format!("Malformed HMAC tag: {}", e)))?;
+        let message = registration_message(file, actual, record)?;
+        let ok = document_hasher_rust::verify_hmac_sha256(key.as_bytes(), &message, &expected)?;
+        println!(
+            "{}: tag {}",
+            file.display(),
+            if ok { "OK" } else { "FAILED" }
+        );
+        matched &= ok;
+    }
+    if matched {
+        Ok(())
+    } else {
+        Err(Failure::Mismatch)
+    }
+}
+
+fn run_check(list: &Path, algorithm: Algorithm, quiet: bool) -> Result<(), Failure> {
+    let mut text = String::new();
+    open_input(list)?
+        .read_to_string(&mut text)
+        .map_err(|e| HasherError::io(list, e))?;
+    let report = checksums::check_manifest(&text, algorithm, Path::new("."));
+    if report.results.is_empty() {
+        return Err(Failure::Error(format!(
+            "{}: no properly formatted checksum lines found",
+            list.display()
+        )));
+    }
+    let mut failures = 0;
+    let mut missing = 0;
+    for result in &report.results {
+        match (result.status, &result.error) {
+            (CheckStatus::Ok, _) if quiet => {}
+            (CheckStatus::Ok, _) => println!("{}: OK", result.path),
+            (CheckStatus::Failed, None) => {
+                println!("{}: FAILED", result.path);
+                failures += 1;
+            }
+            (CheckStatus::Failed, Some(error)) => {
+                println!("{}: FAILED open or read ({})", result.path, error);
+                failures += 1;
+            }
+            (CheckStatus::Missing, _) => {
+                println!("{}: MISSING", result.path);
+                missing += 1;
+            }
+        }
+    }
+    if !report.malformed_lines.is_empty() {
+        eprintln!(
+            "hashsure: WARNING: {} line(s) are improperly formatted",
+            report.malformed_lines.len()
+        );
+    }
+    if missing > 0 {
+        eprintln!(
+            "hashsure: WARNING: {} listed file(s) could not be found",
+            missing
+        );
+    }
+    if failures > 0 {
+        eprintln!(
+            "hashsure: WARNING: {} computed checksum(s) did NOT match",
+            failures
+        );
+    }
+    if report.passed() {
+        Ok(())
+    } else {
+        Err(Failure::Mismatch)
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), Failure> {
+    match &cli.command {
+        Command::Hash {
+            input,
+            algorithm,
+            format,
+            tag,
+        } => run_hash(input, *algorithm, *format, *tag),
+        Command::Hmac {
+            file,
+            record,
+            key,
+            format,
+        } => run_hmac(file, record, key, *format),
+        Command::Verify {
+            file,
+            digest,
+            tag,
+            record,
+            key,
+        } => run_verify(file, digest.as_deref(), tag.as_deref(), record, key),
+        Command::Check {
+            list,
+            algorithm,
+            quiet,
+        } => run_check(list, *algorithm, *quiet),
+    }
+}
+
+/// Maps the outcome of a command to the process exit code, printing the error if there was one.
+fn exit_code(result: Result<(), Failure>) -> u8 {
+    match result {
+        Ok(()) => 0,
+        Err(Failure::Mismatch) => EXIT_MISMATCH,
+        Err(Failure::Error(message)) => {
+            eprintln!("hashsure: {}", message);
+            EXIT_ERROR
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    ExitCode::from(exit_code(run(&Cli::parse())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    /// 2024-05-01 12:00:00 UTC in microseconds since the Unix epoch.
+    const NOON: i64 = 1_714_564_800_000_000;
+    const CONTENT: &[u8] = b"registered document";
+    const KEY: [u8; 32] = [0x42; 32];
+
+    /// Runs `hashsure` with `args` as `main` would, returning the exit code.
+    fn hashsure(args: &[&str]) -> u8 {
+        let cli =
+            Cli::try_parse_from(std::iter::once("hashsure").chain(args.iter().copied())).unwrap();
+        exit_code(run(&cli))
+    }
+
+    fn path_arg(dir: &TempDir, name: &str) -> String {
+        dir.path().join(name).display().to_string()
+    }
+
+    #[test]
+    fn uploaded_at_accepts_microseconds_and_utc_dates() {
+        for value in [
+            "1714564800000000",
+            "2024-05-01 12:00:00",
+            "2024-05-01T12:00:00",
+            "2024-05-01T12:00:00Z",
+            "2024-05-01 12:00:00Z",
+        ] {
+            assert_eq!(parse_uploaded_at(value), Ok(NOON), "{}", value);
+        }
+    }
+
+    #[test]
+    fn uploaded_at_keeps_fractional_seconds() {
+        assert_eq!(
+            parse_uploaded_at("2024-05-01 12:00:00.5"),
+            Ok(NOON + 500_000)
+        );
+        assert_eq!(
+            parse_uploaded_at("2024-05-01 12:00:00.123456"),
+            Ok(NOON + 123_456)
+        );
+        assert_eq!(
+            parse_uploaded_at("2024-05-01T12:00:00.000001Z"),
+            Ok(NOON + 1)
+        );
+    }
+
+    #[test]
+    fn uploaded_at_applies_utc_offsets() {
+        assert_eq!(parse_uploaded_at("2024-05-01T12:00:00+00:00"), Ok(NOON));
+        assert_eq!(parse_uploaded_at("2024-05-01T14:00:00+02:00"), Ok(NOON));
+        assert_eq!(parse_uploaded_at("2024-05-01 07:30:00-04:30"), Ok(NOON));
+        assert_eq!(
+            parse_uploaded_at("2024-05-02T01:00:00.25+13:00"),
+            Ok(NOON + 250_000)
+        );
+    }
+
+    #[test]
+    fn uploaded_at_rejects_malformed_times() {
+        for value in [
+            "",
+            "yesterday",
+            "2024-05-01",
+            "2024-05-01 12:00",
+            "2024/05/01 12:00:00",
+            "2024-13-01 12:00:00",
+            "2024-02-30 12:00:00",
+            "2024-05-01 24:00:00",
+            "2024-05-01 12:00:00.1234567",
+            "2024-05-01 12:00:00.1x",
+            "2024-05-01 12:00:00ZZ",
+            "2024-05-01 12:00:00+2",
+            "2024-05-01 12:00:00+0200",
+            "2024-05-01 12:00:00+24:00",
+            "2024-05-01 12:00:00Z+01:00",
+        ] {
+            assert!(parse_uploaded_at(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn hash_exits_with_an_error_after_hashing_the_readable_files() {
+        let dir = TempDir::new("cli-hash");
+        dir.write("document", CONTENT);
+        let document = path_arg(&dir, "document");
+        let missing = path_arg(&dir, "missing");
+        assert_eq!(hashsure(&["hash", &document]), 0);
+        assert_eq!(hashsure(&["hash", &missing, &document]), EXIT_ERROR);
+        assert_eq!(
+            hashsure(&["hash", "--tag", &document, &missing]),
+            EXIT_ERROR
+        );
+    }
+
+    #[test]
+    fn verify_exit_codes_distinguish_mismatches_from_errors() {
+        let dir = TempDir::new("cli-verify");
+        dir.write("document", CONTENT);
+        dir.write("key", &KEY);
+        let document = path_arg(&dir, "document");
+        let key_file = path_arg(&dir, "key");
+        let digest = document_hasher_rust::sha256(CONTENT);
+        let message = Record::new(digest.clone(), "document", NOON).canonical_bytes();
+        let tag = document_hasher_rust::hmac_sha256(&KEY, &message)
+            .unwrap()
+            .to_hex();
+        let other_digest = document_hasher_rust::sha256(b"other").to_hex();
+        let other_tag = document_hasher_rust::sha256(b"other tag").to_hex();
+
+        assert_eq!(
+            hashsure(&["verify", &document, "--digest", &digest.to_hex()]),
+            0
+        );
+        assert_eq!(
+            hashsure(&["verify", &document, "--digest", &other_digest]),
+            EXIT_MISMATCH
+        );
+        let verify_tag = |tag: &str, key_file: &str| {
+            hashsure(&[
+                "verify",
+                &document,
+                "--tag",
+                tag,
+                "--uploaded-at",
+                "2024-05-01 12:00:00",
+                "--key-file",
+                key_file,
+            ])
+        };
+        assert_eq!(verify_tag(&tag, &key_file), 0);
+        assert_eq!(verify_tag(&other_tag, &key_file), EXIT_MISMATCH);
+
+        assert_eq!(verify_tag(&tag, &path_arg(&dir, "missing-key")), EXIT_ERROR);
+        assert_eq!(verify_tag("not hex", &key_file), EXIT_ERROR);
+        assert_eq!(
+            hashsure(&["verify", &document, "--digest", "not hex"]),
+            EXIT_ERROR
+        );
+        assert_eq!(
+            hashsure(&[
+                "verify",
+                &path_arg(&dir, "missing"),
+                "--digest",
+                &digest.to_hex()
+            ]),
+            EXIT_ERROR
+        );
+    }
+
+    #[test]
+    fn check_exit_codes_distinguish_mismatches_from_errors() {
+        let dir = TempDir::new("cli-check");
+        dir.write("document", CONTENT);
+        let document = path_arg(&dir, "document");
+        let digest = document_hasher_rust::sha256(CONTENT).to_hex();
+        let other_digest = document_hasher_rust::sha256(b"other").to_hex();
+        dir.write("good", format!("{}  {}\n", digest, document).as_bytes());
+        dir.write(
+            "bad",
+            format!("{}  {}\n", other_digest, document).as_bytes(),
+        );
+        dir.write("empty", b"");
+
+        assert_eq!(hashsure(&["check", &path_arg(&dir, "good")]), 0);
+        assert_eq!(hashsure(&["check", &path_arg(&dir, "bad")]), EXIT_MISMATCH);
+        assert_eq!(hashsure(&["check", &path_arg(&dir, "empty")]), EXIT_ERROR);
+        assert_eq!(hashsure(&["check", &path_arg(&dir, "missing")]), EXIT_ERROR);
+    }
+}
@@ -0,0 +1,385 @@
+//! Checksum manifests in the two formats produced by coreutils:
+//!
+//! - GNU (`sha256sum`): `<hex>  <file>` for text mode, `<hex> *<file>` for binary mode;
+//! - BSD tagged (`sha256sum --tag`): `SHA256 (<file>) = <hex>`.
+//!
+//! File names containing a backslash, newline or carriage return are escaped (`\\`, `\n`, `\r`)
+//! and the line is prefixed with a single `\`, as coreutils does. Checking follows `sha256sum -c`:
+//! every entry is reported as OK, FAILED or MISSING, and improperly formatted lines are counted
+//! rather than aborting the check.
+
+use crate::{Algorithm, Digest, HasherError, digest_file};
+use std::io;
+use std::path::Path;
+
+/// Output format for `format_manifest`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestStyle {
+    /// `<hex>  <file>` lines, as printed by `sha256sum`.
+    Gnu,
+    /// `SHA256 (<file>) = <hex>` lines, as printed by `sha256sum --tag`.
+    Bsd,
+}
+
+impl std::str::FromStr for ManifestStyle {
+    type Err = HasherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gnu" => Ok(ManifestStyle::Gnu),
+            "bsd" | "tag" => Ok(ManifestStyle::Bsd),
+            _ => Err(HasherError::InvalidInput(format!(
+                "Unknown manifest style '{}'; expected 'gnu' or 'bsd'",
+                s
+            ))),
+        }
+    }
+}
+
+/// One line of a checksum manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub digest: Digest,
+    /// Whether the GNU binary-mode marker (`*`) was used. Has no effect on the digest.
+    pub binary: bool,
+}
+
+/// The entries of a parsed manifest, plus the 1-based numbers of lines that could not be parsed.
+#[derive(Debug, Clone, Default)]
+pub struct ParsedManifest {
+    pub entries: Vec<ManifestEntry>,
+    pub malformed_lines: Vec<usize>,
+}
+
+/// The BSD tag for an algorithm, e.g. `SHA256` or `SHA3-256`.
+pub fn bsd_tag(algorithm: Algorithm) -> String {
+    algorithm.name().to_ascii_uppercase().replace('_', "-")
+}
+
+pub(crate) fn escape_name(name: &str) -> (bool, String) {
+    if !name.contains(['\\', '\n', '\r']) {
+        return (false, name.to_string());
+    }
+    let escaped = name
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+    (true, escaped)
+}
+
+pub(crate) fn unescape_name(name: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => unescaped.push('\\'),
+            'n' => unescaped.push('\n'),
+            'r' => unescaped.push('\r'),
+            _ => return None,
+        }
+    }
+    Some(unescaped)
+}
+
+/// Formats a single manifest line (without the trailing newline).
+pub fn format_line(entry: &ManifestEntry, style: ManifestStyle) -> String {
+    let (escaped, name) = escape_name(&entry.path);
+    let prefix = if escaped { "\\" } else { "" };
+    match style {
+        ManifestStyle::Gnu => {
+            let marker = if entry.binary { '*' } else { ' ' };
+            format!("{}{} {}{}", prefix, entry.digest.to_hex(), marker, name)
+        }
+        ManifestStyle::Bsd => {
+            format!(
+                "{}{} ({}) = {}",
+                prefix,
+                bsd_tag(entry.digest.algorithm()),
+                name,
+                entry.digest.to_hex()
+            )
+        }
+    }
+}
+
+/// Formats entries as a manifest, one line per entry.
+pub fn format_manifest(entries: &[ManifestEntry], style: ManifestStyle) -> String {
+    entries
+        .iter()
+        .map(|entry| format_line(entry, style) + "\n")
+        .collect()
+}
+
+/// Hashes each file and formats the results as a manifest. Paths are written as given.
+pub fn generate_manifest<P: AsRef<Path>>(
+    paths: &[P],
+    algorithm: Algorithm,
+    style: ManifestStyle,
+) -> Result<String, HasherError> {
+    let entries = paths
+        .iter()
+        .map(|path| {
+            let path = path.as_ref();
+            Ok(ManifestEntry {
+                path: path.to_string_lossy().into_owned(),
+                digest: digest_file(algorithm, path)?,
+                binary: false,
+            })
+        })
+        .collect::<Result<Vec<_>, HasherError>>()?;
+    Ok(format_manifest(&entries, style))
+}
+
+/// Parses one manifest line. Untagged (GNU) lines are read as `default_algorithm` digests.
+pub fn parse_line(line: &str, default_algorithm: Algorithm) -> Option<ManifestEntry> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (name, algorithm, hex, binary) = match parse_bsd(line) {
+        Some((name, algorithm, hex)) => (name, algorithm, hex, false),
+        None => {
+            let (hex, rest) = line.split_at_checked(default_algorithm.output_size() * 2)?;
+            let binary = match rest.get(..2)? {
+                "  " => false,
+                " *" => true,
+                _ => return None,
+            };
+            (&rest[2..], default_algorithm, hex, binary)
+        }
+    };
+    if name.is_empty() {
+        return None;
+    }
+    let path = if escaped {
+        unescape_name(name)?
+    } else {
+        name.to_string()
+    };
+    let digest = Digest::from_hex(algorithm, hex).ok()?;
+    Some(ManifestEntry {
+        path,
+        digest,
+        binary,
+    })
+}
+
+fn parse_bsd(line: &str) -> Option<(&str, Algorithm, &str)> {
+    let (tag, rest) = line.split_once(" (")?;
+    let (name, hex) = rest.rsplit_once(") = ")?;
+    let algorithm = tag.parse().ok()?;
+    Some((name, algorithm, hex))
+}
+
+/// Parses a manifest in either format (they may be mixed). Blank lines and `#` comments are skipped.
+pub fn parse_manifest(text: &str, default_algorithm: Algorithm) -> ParsedManifest {
+    let mut parsed = ParsedManifest::default();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_line(line, default_algorithm) {
+            Some(entry) => parsed.entries.push(entry),
+            None => parsed.malformed_lines.push(index + 1),
+        }
+    }
+    parsed
+}
+
+/// Outcome of checking one manifest entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    /// The file's digest matches.
+    Ok,
+    /// The file's digest differs, or the file could not be read.
+    Failed,
+    /// The file does not exist.
+    Missing,
+}
+
+impl CheckStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CheckStatus::Ok => "OK",
+            CheckStatus::Failed => "FAILED",
+            CheckStatus::Missing => "MISSING",
+        }
+    }
+}
+
+/// The result of checking one manifest entry; `error` explains a read failure.
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub path: String,
+    pub status: CheckStatus,
+    pub error: Option<String>,
+}
+
+/// The results of `check_manifest`, in manifest order.
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+    pub results: Vec<CheckResult>,
+    pub malformed_lines: Vec<usize>,
+}
+
+impl CheckReport {
+    /// Whether there was at least one entry and every entry was OK. Like `sha256sum -c` without
+    /// `--strict`, improperly formatted lines alone don't fail the check, but a manifest without a
+    /// single properly formatted line does.
+    pub fn passed(&self) -> bool {
+        !self.results.is_empty()
+            && self
+                .results
+                .iter()
+                .all(|result| result.status == CheckStatus::Ok)
+    }
+}
+
+/// Verifies every entry of a manifest, resolving relative paths against `base_dir`.
+pub fn check_manifest(text: &str, default_algorithm: Algorithm, base_dir: &Path) -> CheckReport {
+    let parsed = parse_manifest(text, default_algorithm);
+    let results = parsed
+        .entries
+        .into_iter()
+        .map(|entry| {
+            let (status, error) =
+                match digest_file(entry.digest.algorithm(), base_dir.join(&entry.path)) {
+                    Ok(actual) if actual == entry.digest => (CheckStatus::Ok, None),
+                    Ok(_) => (CheckStatus::Failed, None),
+                    Err(HasherError::Io { source, .. })
+                        if source.kind() == io::ErrorKind::NotFound =>
+                    {
+                        (CheckStatus::Missing, Some(source.to_string()))
+                    }
+                    Err(e) => (CheckStatus::Failed, Some(e.to_string())),
+                };
+            CheckResult {
+                path: entry.path,
+                status,
+                error,
+            }
+        })
+        .collect();
+    CheckReport {
+        results,
+        malformed_lines: parsed.malformed_lines,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    fn abc() -> Digest {
+        Digest::from_hex(Algorithm::Sha256, ABC_SHA256).unwrap()
+    }
+
+    #[test]
+    fn parses_gnu_lines_in_text_and_binary_mode() {
+        let text = parse_line(&format!("{}  a b.txt", ABC_SHA256), Algorithm::Sha256).unwrap();
+        assert_eq!(text.path, "a b.txt");
+        assert_eq!(text.digest, abc());
+        assert!(!text.binary);
+        let binary = parse_line(&format!("{} *a.bin", ABC_SHA256), Algorithm::Sha256).unwrap();
+        assert_eq!(binary.path, "a.bin");
+        assert!(binary.binary);
+        assert_eq!(
+            format_line(&binary, ManifestStyle::Gnu),
+            format!("{} *a.bin", ABC_SHA256)
+        );
+    }
+
+    #[test]
+    fn parses_bsd_tagged_lines_with_their_own_algorithm() {
+        let sha3 = Algorithm::Sha3_256.digest(b"abc");
+        let line = format!("SHA3-256 (x (1).txt) = {}", sha3.to_hex());
+        let entry = parse_line(&line, Algorithm::Sha256).unwrap();
+        assert_eq!(entry.path, "x (1).txt");
+        assert_eq!(entry.digest, sha3);
+        assert_eq!(format_line(&entry, ManifestStyle::Bsd), line);
+        let sha256 = parse_line(&format!("SHA256 (a) = {}", ABC_SHA256), Algorithm::Sha3_256);
+        assert_eq!(sha256.unwrap().digest, abc());
+    }
+
+    #[test]
+    fn escaped_names_round_trip() {
+        let entry = ManifestEntry {
+            path: "dir\\new\nline\r.txt".to_string(),
+            digest: abc(),
+            binary: false,
+        };
+        let gnu = format_line(&entry, ManifestStyle::Gnu);
+        assert_eq!(gnu, format!("\\{}  dir\\\\new\\nline\\r.txt", ABC_SHA256));
+        assert_eq!(parse_line(&gnu, Algorithm::Sha256).unwrap(), entry);
+        let bsd = format_line(&entry, ManifestStyle::Bsd);
+        assert!(bsd.starts_with("\\SHA256 ("));
+        assert_eq!(parse_line(&bsd, Algorithm::Sha256).unwrap(), entry);
+        // With the `\` line prefix an unknown escape is rejected; without it, backslashes are literal.
+        assert!(parse_line(&format!("\\{}  a\\tb", ABC_SHA256), Algorithm::Sha256).is_none());
+        let unprefixed = parse_line(&format!("{}  a\\nb", ABC_SHA256), Algorithm::Sha256);
+        assert_eq!(unprefixed.unwrap().path, "a\\nb");
+    }
+
+    #[test]
+    fn malformed_lines_are_counted_and_comments_skipped() {
+        let text = format!(
+            "# comment\n\n{abc}  good\r\n{abc} bad-separator\n{short}  short\nMD4 (x) = {abc}\n{abc}  \n",
+            abc = ABC_SHA256,
+            short = &ABC_SHA256[..62],
+        );
+        let parsed = parse_manifest(&text, Algorithm::Sha256);
+        assert_eq!(parsed.entries.len(), 1);
+        assert_eq!(parsed.entries[0].path, "good");
+        assert_eq!(parsed.malformed_lines, [4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn check_reports_ok_failed_and_missing() {
+        let dir = TempDir::new("checksums-check");
+        dir.write("good", b"abc");
+        dir.write("changed", b"abd");
+        let text = format!(
+            "{abc}  good\n{abc}  changed\n{abc}  missing\nnot a checksum line\n",
+            abc = ABC_SHA256
+        );
+        let report = check_manifest(&text, Algorithm::Sha256, dir.path());
+        let statuses: Vec<_> = report
+            .results
+            .iter()
+            .map(|result| (result.path.as_str(), result.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("good", CheckStatus::Ok),
+                ("changed", CheckStatus::Failed),
+                ("missing", CheckStatus::Missing),
+            ]
+        );
+        assert!(report.results[2].error.is_some());
+        assert_eq!(report.malformed_lines, [4]);
+        assert!(!report.passed());
+
+        let only_good = format!("{}  good\nnot a checksum line\n", ABC_SHA256);
+        assert!(check_manifest(&only_good, Algorithm::Sha256, dir.path()).passed());
+    }
+
+    #[test]
+    fn empty_or_entirely_malformed_manifest_fails() {
+        let dir = TempDir::new("checksums-empty");
+        for text in ["", "# only a comment\n", "not a checksum line\n"] {
+            let report = check_manifest(text, Algorithm::Sha256, dir.path());
+            assert!(report.results.is_empty());
+            assert!(!report.passed(), "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,432 @@
+//! Compact binary registration receipts as COSE (RFC 9052) structures, small enough for QR codes
+//! and embedded metadata.
+//!
+//! A receipt is either a tagged `COSE_Sign1` signed with Ed25519 (`alg` EdDSA), verifiable with the
+//! public key alone, or a tagged `COSE_Mac0` authenticated with HMAC-SHA256 (`alg` HMAC 256/256).
+//! The protected header carries `alg` and the issuer's key id (`kid`); the payload is a CBOR map:
+//!
+//! ```text
+//! { 1: hash algorithm (COSE algorithm id, e.g. -16 for SHA-256),
+//!   2: digest (bstr),
+//!   3: registration time (uint, Unix seconds),
+//!   ? 4: file name (tstr) }
+//! ```
+
+use crate::ed25519::{Ed25519PrivateKey, Ed25519PublicKey};
+use crate::{Algorithm, Digest, HasherError};
+use coset::cbor::value::Value;
+use coset::{
+    CoseMac0, CoseMac0Builder, CoseSign1, CoseSign1Builder, HeaderBuilder,
+    RegisteredLabelWithPrivate, TaggedCborSerializable, iana,
+};
+
+const HASH_ALGORITHM_LABEL: i64 = 1;
+const DIGEST_LABEL: i64 = 2;
+const TIMESTAMP_LABEL: i64 = 3;
+const FILE_NAME_LABEL: i64 = 4;
+
+/// The kind of COSE structure a receipt was encoded as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoseReceiptKind {
+    /// `COSE_Sign1` with an Ed25519 signature.
+    Sign1,
+    /// `COSE_Mac0` with an HMAC-SHA256 tag.
+    Mac0,
+}
+
+impl CoseReceiptKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CoseReceiptKind::Sign1 => "sign1",
+            CoseReceiptKind::Mac0 => "mac0",
+        }
+    }
+}
+
+/// The claims carried by a COSE receipt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoseReceipt {
+    pub digest: Digest,
+    pub timestamp: u64,
+    pub key_id: String,
+    pub file_name: Option<String>,
+}
+
+/// The COSE algorithm id for a digest algorithm. SHA3-256 and BLAKE3 have none registered.
+fn cose_hash_algorithm(algorithm: Algorithm) -> Result<iana::Algorithm, HasherError> {
+    match algorithm {
+        Algorithm::Sha256 => Ok(iana::Algorithm::SHA_256),
+        Algorithm::Sha384 => Ok(iana::Algorithm::SHA_384),
+        Algorithm::Sha512 => Ok(iana::Algorithm::SHA_512),
+        Algorithm::Sha512_256 => Ok(iana::Algorithm::SHA_512_256),
+        Algorithm::Sha3_256 | Algorithm::Blake3 => Err(HasherError::UnsupportedAlgorithm(format!(
+            "{} has no COSE algorithm identifier",
+            algorithm.name()
+        ))),
+    }
+}
+
+fn hash_algorithm_from_cose(id: i128) -> Option<Algorithm> {
+    Algorithm::ALL.iter().copied().find(|&algorithm| {
+        cose_hash_algorithm(algorithm).is_ok_and(|cose| i128::from(cose as i64) == id)
+    })
+}
+
+fn malformed(reason: impl std::fmt::Display) -> HasherError {
+    HasherError::InvalidDigest(format!("Malformed COSE receipt: {}", reason))
+}
+
+impl CoseReceipt {
+    fn payload(&self) -> Result<Vec<u8>, HasherError> {
+        let mut claims = vec![
+            (
+                Value::from(HASH_ALGORITHM_LABEL),
+                Value::from(cose_hash_algorithm(self.digest.algorithm())? as i64),
+            ),
+            (
+                Value::from(DIGEST_LABEL),
+                Value::Bytes(self.digest.as_bytes().to_vec()),
+            ),
+            (Value::from(TIMESTAMP_LABEL), Value::from(self.timestamp)),
+        ];
+        if let Some(file_name) = &self.file_name {
+            claims.push((Value::from(FILE_NAME_LABEL), Value::Text(file_name.clone())));
+        }
+        let mut payload = Vec::new();
+        coset::cbor::ser::into_writer(&Value::Map(claims), &mut payload).map_err(malformed)?;
+        Ok(payload)
+    }
+
+    fn from_payload(payload: &[u8], key_id: &[u8]) -> Result<Self, HasherError> {
+        let value: Value = coset::cbor::de::from_reader(payload).map_err(malformed)?;
+        let Value::Map(claims) = value else {
+            return Err(malformed("payload is not a map"));
+        };
+        let claim = |label: i64| {
+            claims
+                .iter()
+                .find(|(key, _)| {
+                    key.as_integer()
+                        .is_some_and(|key| i128::from(key) == i128::from(label))
+                })
+                .map(|(_, value)| value)
+        };
+        let hash_algorithm = claim(HASH_ALGORITHM_LABEL)
+            .and_then(Value::as_integer)
+            .and_then(|id| hash_algorithm_from_cose(i128::from(id)))
+            .ok_or_else(|| malformed("missing or unsupported hash algorithm"))?;
+        let digest = claim(DIGEST_LABEL)
+            .and_then(Value::as_bytes)
+            .ok_or_else(|| malformed("missing digest"))?;
+        if digest.len() != hash_algorithm.output_size() {
+            return Err(malformed(format!(
+                "{} digest must be {} bytes, got {}",
+                hash_algorithm.name(),
+                hash_algorithm.output_size(),
+                digest.len()
+            )));
+        }
+        let timestamp = claim(TIMESTAMP_LABEL)
+            .and_then(Value::as_integer)
+            .and_then(|timestamp| u64::try_from(timestamp).ok())
+            .ok_or_else(|| malformed("missing timestamp"))?;
+        let file_name = match claim(FILE_NAME_LABEL) {
+            Some(value) => Some(
+                value
+                    .as_text()
+                    .ok_or_else(|| malformed("file name is not text"))?
+                    .to_string(),
+            ),
+            None => None,
+        };
+        let key_id =
+            String::from_utf8(key_id.to_vec()).map_err(|_| malformed("key id is not UTF-8"))?;
+        Ok(CoseReceipt {
+            digest: Digest::new(hash_algorithm, digest.clone()),
+            timestamp,
+            key_id,
+            file_name,
+        })
+    }
+
+    /// Encodes the receipt as a tagged `COSE_Sign1` signed with `private_key`.
+    pub fn sign_ed25519(&self, private_key: &Ed25519PrivateKey) -> Result<Vec<u8>, HasherError> {
+        let protected = HeaderBuilder::new()
+            .algorithm(iana::Algorithm::EdDSA)
+            .key_id(self.key_id.as_bytes().to_vec())
+            .build();
+        CoseSign1Builder::new()
+            .protected(protected)
+            .payload(self.payload()?)
+            .create_signature(&[], |data| private_key.sign(data).to_vec())
+            .build()
+            .to_tagged_vec()
+            .map_err(malformed)
+    }
+
+    /// Encodes the receipt as a tagged `COSE_Mac0` authenticated with HMAC-SHA256 under `secret_key`.
+    pub fn mac_hmac_sha256(&self, secret_key: &[u8]) -> Result<Vec<u8>, HasherError> {
+        let protected = HeaderBuilder::new()
+            .algorithm(iana::Algorithm::HMAC_256_256)
+            .key_id(self.key_id.as_bytes().to_vec())
+            .build();
+        CoseMac0Builder::new()
+            .protected(protected)
+            .payload(self.payload()?)
+            .try_create_tag(&[], |data| {
+                Algorithm::Sha256
+                    .hmac(secret_key, data)
+                    .map(Digest::into_bytes)
+            })?
+            .build()
+            .to_tagged_vec()
+            .map_err(malformed)
+    }
+}
+
+enum CoseMessage {
+    Sign1(CoseSign1),
+    Mac0(CoseMac0),
+}
+
+/// A decoded COSE receipt. Its claims are untrusted until `verify_ed25519` or `verify_hmac_sha256` succeeds.
+pub struct DecodedCoseReceipt {
+    receipt: CoseReceipt,
+    message: CoseMessage,
+}
+
+impl DecodedCoseReceipt {
+    /// Decodes a tagged `COSE_Sign1` or `COSE_Mac0` receipt.
+    pub fn decode(data: &[u8]) -> Result<Self, HasherError> {
+        let message =
+            match CoseSign1::from_tagged_slice(data) {
+                Ok(sign1) => CoseMessage::Sign1(sign1),
+                Err(_) => CoseMessage::Mac0(CoseMac0::from_tagged_slice(data).map_err(|_| {
+                    malformed("expected a tagged COSE_Sign1 or COSE_Mac0 structure")
+                })?),
+            };
+        let (protected, payload) = match &message {
+            CoseMessage::Sign1(sign1) => (&sign1.protected.header, &sign1.payload),
+            CoseMessage::Mac0(mac0) => (&mac0.protected.header, &mac0.payload),
+        };
+        let payload = payload
+            .as_deref()
+            .ok_or_else(|| malformed("missing payload"))?;
+        let receipt = CoseReceipt::from_payload(payload, &protected.key_id)?;
+        Ok(DecodedCoseReceipt { receipt, message })
+    }
+
+    pub fn kind(&self) -> CoseReceiptKind {
+        match self.message {
+            CoseMessage::Sign1(_) => CoseReceiptKind::Sign1,
+            CoseMessage::Mac0(_) => CoseReceiptKind::Mac0,
+        }
+    }
+
+    /// The receipt's claims. Only trust them after verification has returned true.
+    pub fn receipt(&self) -> &CoseReceipt {
+        &self.receipt
+    }
+
+    /// Verifies a `COSE_Sign1` receipt's Ed25519 signature. Returns false for an invalid signature;
+    /// raises InvalidKey if the receipt is a `COSE_Mac0` or names another algorithm.
+    pub fn verify_ed25519(&self, public_key: &Ed25519PublicKey) -> Result<bool, HasherError> {
+        let CoseMessage::Sign1(sign1) = &self.message else {
+            return Err(HasherError::InvalidKey(
+                "COSE_Mac0 receipts are verified with an HMAC key".to_string(),
+            ));
+        };
+        require_algorithm(&sign1.protected.header.alg, iana::Algorithm::EdDSA)?;
+        public_key.verify(&sign1.tbs_data(&[]), &sign1.signature)
+    }
+
+    /// Verifies a `COSE_Mac0` receipt's HMAC-SHA256 tag in constant time. Returns false for an invalid tag;
+    /// raises InvalidKey if the receipt is a `COSE_Sign1` or names another algorithm.
+    pub fn verify_hmac_sha256(&self, secret_key: &[u8]) -> Result<bool, HasherError> {
+        let CoseMessage::Mac0(mac0) = &self.message else {
+            return Err(HasherError::InvalidKey(
+                "COSE_Sign1 receipts are verified with an Ed25519 public key".to_string(),
+            ));
+        };
+        require_algorithm(&mac0.protected.header.alg, iana::Algorithm::HMAC_256_256)?;
+        let tbm = coset::mac_structure_data(
+            coset::MacContext::CoseMac0,
+            mac0.protected.clone(),
+            &[],
+            mac0.payload.as_deref().unwrap_or_default(),
+        );
+        Algorithm::Sha256.verify_hmac(secret_key, &tbm, &mac0.tag)
+    }
+}
+
+/// Rejects a receipt whose protected `alg` is not the one its structure and key imply.
+fn require_algorithm(
+    alg: &Option<coset::Algorithm>,
+    expected: iana::Algorithm,
+) -> Result<(), HasherError> {
+    match alg {
+        Some(RegisteredLabelWithPrivate::Assigned(alg)) if *alg == expected => Ok(()),
+        _ => Err(HasherError::InvalidKey(format!(
+            "COSE receipt algorithm must be {:?}",
+            expected
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use coset::CborSerializable;
+
+    const HMAC_KEY: &[u8] = &[b'k'; 32];
+
+    fn private_key() -> Ed25519PrivateKey {
+        Ed25519PrivateKey::from_raw(&[7; 32]).unwrap()
+    }
+
+    fn receipt() -> CoseReceipt {
+        CoseReceipt {
+            digest: crate::sha256(b"contract"),
+            timestamp: 1_700_000_000,
+            key_id: "2024-01".to_string(),
+            file_name: Some("contract.pdf".to_string()),
+        }
+    }
+
+    /// Another receipt's payload, to swap into a signed structure.
+    fn other_payload() -> Vec<u8> {
+        CoseReceipt {
+            timestamp: 1_700_000_001,
+            ..receipt()
+        }
+        .payload()
+        .unwrap()
+    }
+
+    /// Decodes a tagged `COSE_Sign1`, lets `edit` change it and re-encodes it with the original signature.
+    fn edit_sign1(data: &[u8], edit: impl FnOnce(&mut CoseSign1)) -> Vec<u8> {
+        let mut sign1 = CoseSign1::from_tagged_slice(data).unwrap();
+        edit(&mut sign1);
+        sign1.to_tagged_vec().unwrap()
+    }
+
+    fn edit_mac0(data: &[u8], edit: impl FnOnce(&mut CoseMac0)) -> Vec<u8> {
+        let mut mac0 = CoseMac0::from_tagged_slice(data).unwrap();
+        edit(&mut mac0);
+        mac0.to_tagged_vec().unwrap()
+    }
+
+    #[test]
+    fn sign1_round_trips_and_verifies() {
+        let data = receipt().sign_ed25519(&private_key()).unwrap();
+        assert_eq!(data[0], 0xd2, "tag 18, COSE_Sign1");
+        let decoded = DecodedCoseReceipt::decode(&data).unwrap();
+        assert_eq!(decoded.kind(), CoseReceiptKind::Sign1);
+        assert_eq!(decoded.receipt(), &receipt());
+        assert!(decoded.verify_ed25519(&private_key().public_key()).unwrap());
+        assert!(decoded.verify_hmac_sha256(HMAC_KEY).is_err());
+
+        let without_name = CoseReceipt {
+            file_name: None,
+            ..receipt()
+        };
+        let data = without_name.sign_ed25519(&private_key()).unwrap();
+        assert_eq!(
+            DecodedCoseReceipt::decode(&data).unwrap().receipt(),
+            &without_name
+        );
+    }
+
+    #[test]
+    fn mac0_round_trips_and_verifies() {
+        let data = receipt().mac_hmac_sha256(HMAC_KEY).unwrap();
+        assert_eq!(data[0], 0xd1, "tag 17, COSE_Mac0");
+        let decoded = DecodedCoseReceipt::decode(&data).unwrap();
+        assert_eq!(decoded.kind(), CoseReceiptKind::Mac0);
+        assert_eq!(decoded.receipt(), &receipt());
+        assert!(decoded.verify_hmac_sha256(HMAC_KEY).unwrap());
+        assert!(decoded.verify_ed25519(&private_key().public_key()).is_err());
+    }
+
+    #[test]
+    fn wrong_key_does_not_verify() {
+        let data = receipt().sign_ed25519(&private_key()).unwrap();
+        let other = Ed25519PrivateKey::from_raw(&[8; 32]).unwrap().public_key();
+        let decoded = DecodedCoseReceipt::decode(&data).unwrap();
+        assert!(!decoded.verify_ed25519(&other).unwrap());
+
+        let data = receipt().mac_hmac_sha256(HMAC_KEY).unwrap();
+        let decoded = DecodedCoseReceipt::decode(&data).unwrap();
+        assert!(!decoded.verify_hmac_sha256(&[b'j'; 32]).unwrap());
+    }
+
+    #[test]
+    fn modified_payload_does_not_verify() {
+        let public_key = private_key().public_key();
+        let data = receipt().sign_ed25519(&private_key()).unwrap();
+        let edited = edit_sign1(&data, |sign1| sign1.payload = Some(other_payload()));
+        let decoded = DecodedCoseReceipt::decode(&edited).unwrap();
+        assert_eq!(decoded.receipt().timestamp, 1_700_000_001);
+        assert!(!decoded.verify_ed25519(&public_key).unwrap());
+
+        let data = receipt().mac_hmac_sha256(HMAC_KEY).unwrap();
+        let edited = edit_mac0(&data, |mac0| mac0.payload = Some(other_payload()));
+        let decoded = DecodedCoseReceipt::decode(&edited).unwrap();
+        assert!(!decoded.verify_hmac_sha256(HMAC_KEY).unwrap());
+    }
+
+    #[test]
+    fn modified_protected_header_does_not_verify() {
+        let public_key = private_key().public_key();
+        let data = receipt().sign_ed25519(&private_key()).unwrap();
+        let edited = edit_sign1(&data, |sign1| {
+            sign1.protected.header.key_id = b"2024-02".to_vec();
+            sign1.protected.original_data = None;
+        });
+        let decoded = DecodedCoseReceipt::decode(&edited).unwrap();
+        assert_eq!(decoded.receipt().key_id, "2024-02");
+        assert!(!decoded.verify_ed25519(&public_key).unwrap());
+
+        let data = receipt().mac_hmac_sha256(HMAC_KEY).unwrap();
+        let edited = edit_mac0(&data, |mac0| {
+            mac0.protected.header.key_id = b"2024-02".to_vec();
+            mac0.protected.original_data = None;
+        });
+        let decoded = DecodedCoseReceipt::decode(&edited).unwrap();
+        assert!(!decoded.verify_hmac_sha256(HMAC_KEY).unwrap());
+
+        // Swapping the algorithm is refused outright rather than verified under another algorithm.
+        let edited = edit_mac0(&data, |mac0| {
+            mac0.protected.header.alg = Some(RegisteredLabelWithPrivate::Assigned(
+                iana::Algorithm::HMAC_384_384,
+            ));
+            mac0.protected.original_data = None;
+        });
+        let decoded = DecodedCoseReceipt::decode(&edited).unwrap();
+        assert!(matches!(
+            decoded.verify_hmac_sha256(HMAC_KEY),
+            Err(HasherError::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_receipts_are_rejected() {
+        assert!(DecodedCoseReceipt::decode(b"not cbor").is_err());
+        let data = receipt().sign_ed25519(&private_key()).unwrap();
+        let untagged = CoseSign1::from_tagged_slice(&data)
+            .unwrap()
+            .to_vec()
+            .unwrap();
+        assert!(DecodedCoseReceipt::decode(&untagged).is_err());
+        let detached = edit_sign1(&data, |sign1| sign1.payload = None);
+        assert!(DecodedCoseReceipt::decode(&detached).is_err());
+        let sha3 = CoseReceipt {
+            digest: Algorithm::Sha3_256.digest(b"contract"),
+            ..receipt()
+        };
+        assert!(matches!(
+            sha3.sign_ed25519(&private_key()),
+            Err(HasherError::UnsupportedAlgorithm(_))
+        ));
+    }
+}
@@ -0,0 +1,77 @@
+use crate::algorithm::Algorithm;
+use crate::error::HasherError;
+use std::fmt;
+use subtle::ConstantTimeEq;
+
+/// A digest or HMAC tag, together with the algorithm that produced it.
+/// Equality is checked in constant time, so tags can be compared directly.
+#[derive(Clone)]
+pub struct Digest {
+    algorithm: Algorithm,
+    bytes: Vec<u8>,
+}
+
+impl Digest {
+    pub fn new(algorithm: Algorithm, bytes: Vec<u8>) -> Self {
+        Digest { algorithm, bytes }
+    }
+
+    /// Parses a hex-encoded digest, checking that it has the right length for `algorithm`.
+    pub fn from_hex(algorithm: Algorithm, value: &str) -> Result<Self, HasherError> {
+        let bytes = hex::decode(value).map_err(|e| {
+            HasherError::InvalidDigest(format!("Malformed {} digest: {}", algorithm, e))
+        })?;
+        if bytes.len() != algorithm.output_size() {
+            return Err(HasherError::InvalidDigest(format!(
+                "Malformed {} digest: expected {} bytes, got {}",
+                algorithm,
+                algorithm.output_size(),
+                bytes.len()
+            )));
+        }
+        Ok(Digest { algorithm, bytes })
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// The digest as a lowercase hex string.
+    pub fn to_hex(&self) -> String {
+        hex::encode(&self.bytes)
+    }
+}
+
+impl PartialEq for Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.algorithm == other.algorithm && bool::from(self.bytes.ct_eq(&other.bytes))
+    }
+}
+
+impl Eq for Digest {}
+
+impl AsRef<[u8]> for Digest {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Digest({}:{})", self.algorithm, self.to_hex())
+    }
+}
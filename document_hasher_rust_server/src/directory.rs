@@ -0,0 +1,455 @@
+//! HMAC-authenticated manifests of a directory of documents, so a whole folder can be registered as a unit.
+//!
+//! A manifest lists every regular file under the directory as `<size> <sha256 hex> <path>`, sorted by
+//! path, and ends with one HMAC-SHA256 tag over everything before it:
+//!
+//! ```text
+//! hashsure-directory-manifest v1
+//! 5 2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824 contracts/a.pdf
+//! 11 b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9 notes.txt
+//! hmac-sha256 <hex>
+//! ```
+//!
+//! Paths are relative to the directory, use `/` as the separator and escape `\`, newline and carriage
+//! return as in `sha256sum` manifests. Symbolic links and other non-regular files are not listed.
+
+use crate::checksums::{escape_name, unescape_name};
+use crate::{Algorithm, BatchInput, Digest, HasherError};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// First line of every manifest; bumped if the format ever changes.
+pub const MANIFEST_HEADER: &str = "hashsure-directory-manifest v1";
+
+const TAG_PREFIX: &str = "hmac-sha256 ";
+
+/// One file listed in a manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestFile {
+    /// Path relative to the manifest's directory, `/`-separated.
+    pub path: String,
+    pub size: u64,
+    pub digest: Digest,
+}
+
+/// The files of a directory, sorted by path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirectoryManifest {
+    files: Vec<ManifestFile>,
+}
+
+/// How a directory differs from its manifest.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// `(old path, new path)` pairs: a listed file is gone and an unlisted one has the same size and digest.
+    pub renamed: Vec<(String, String)>,
+    pub modified: Vec<String>,
+}
+
+impl ManifestDiff {
+    /// Whether the directory matches the manifest exactly.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.renamed.is_empty()
+            && self.modified.is_empty()
+    }
+}
+
+impl DirectoryManifest {
+    /// Builds a manifest from a list of files, rejecting duplicate paths.
+    pub fn from_files(mut files: Vec<ManifestFile>) -> Result<Self, HasherError> {
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        if let Some(pair) = files.windows(2).find(|pair| pair[0].path == pair[1].path) {
+            return Err(HasherError::InvalidInput(format!(
+                "Duplicate manifest path '{}'",
+                pair[0].path
+            )));
+        }
+        if let Some(file) = files
+            .iter()
+            .find(|file| file.digest.algorithm() != Algorithm::Sha256)
+        {
+            return Err(HasherError::InvalidInput(format!(
+                "Manifest digest for '{}' is not SHA256",
+                file.path
+            )));
+        }
+        Ok(DirectoryManifest { files })
+    }
+
+    /// Walks `root` recursively and hashes every regular file in it, in parallel.
+    pub fn from_directory(root: impl AsRef<Path>) -> Result<Self, HasherError> {
+        let root = root.as_ref();
+        let mut found = Vec::new();
+        collect_files(root, root, &mut found)?;
+        let inputs: Vec<BatchInput<'_>> = found
+            .iter()
+            .map(|(_, full_path, _)| BatchInput::Path(full_path))
+            .collect();
+        let digests = crate::sha256_many(&inputs, None)?;
+        let files = found
+            .into_iter()
+            .zip(digests)
+            .map(|((path, _, size), digest)| {
+                Ok(ManifestFile {
+                    path,
+                    size,
+                    digest: digest?,
+                })
+            })
+            .collect::<Result<Vec<_>, HasherError>>()?;
+        Self::from_files(files)
+    }
+
+    /// The listed files, sorted by path.
+    pub fn files(&self) -> &[ManifestFile] {
+        &self.files
+    }
+
+    /// The canonical manifest text without the tag line; this is what the tag covers.
+    fn body(&self) -> String {
+        let mut body = format!("{}\n", MANIFEST_HEADER);
+        for file in &self.files {
+            body.push_str(&format!(
+                "{} {} {}\n",
+                file.size,
+                file.digest.to_hex(),
+                escape_name(&file.path).1
+            ));
+        }
+        body
+    }
+
+    /// Serializes the manifest and authenticates it as a whole with HMAC-SHA256 under `secret_key`.
+    pub fn sign(&self, secret_key: &[u8]) -> Result<String, HasherError> {
+        let body = self.body();
+        let tag = Algorithm::Sha256.hmac(secret_key, body.as_bytes())?;
+        Ok(format!("{}{}{}\n", body, TAG_PREFIX, tag.to_hex()))
+    }
+
+    /// Compares this manifest (as registered) against `current` (as found now).
+    pub fn diff(&self, current: &DirectoryManifest) -> ManifestDiff {
+        let registered: BTreeMap<&str, &ManifestFile> = self
+            .files
+            .iter()
+            .map(|file| (file.path.as_str(), file))
+            .collect();
+        let found: BTreeMap<&str, &ManifestFile> = current
+            .files
+            .iter()
+            .map(|file| (file.path.as_str(), file))
+            .collect();
+        let mut diff = ManifestDiff::default();
+
+        // Unlisted files by content, so a missing file can be matched to its new name.
+        let mut candidates: HashMap<(u64, String), Vec<&str>> = HashMap::new();
+        for file in current
+            .files
+            .iter()
+            .rev()
+            .filter(|file| !registered.contains_key(file.path.as_str()))
+        {
+            candidates
+                .entry((file.size, file.digest.to_hex()))
+                .or_default()
+                .push(&file.path);
+        }
+        for file in &self.files {
+            match found.get(file.path.as_str()) {
+                Some(now) if now.size == file.size && now.digest == file.digest => {}
+                Some(_) => diff.modified.push(file.path.clone()),
+                None => match candidates
+                    .get_mut(&(file.size, file.digest.to_hex()))
+                    .and_then(Vec::pop)
+                {
+                    Some(new_path) => diff.renamed.push((file.path.clone(), new_path.to_string())),
+                    None => diff.removed.push(file.path.clone()),
+                },
+            }
+        }
+        let renamed_to: Vec<&str> = diff
+            .renamed
+            .iter()
+            .map(|(_, new_path)| new_path.as_str())
+            .collect();
+        diff.added = current
+            .files
+            .iter()
+            .filter(|file| {
+                !registered.contains_key(file.path.as_str())
+                    && !renamed_to.contains(&file.path.as_str())
+            })
+            .map(|file| file.path.clone())
+            .collect();
+        diff
+    }
+}
+
+/// Collects `(relative path, full path, size)` for every regular file under `dir`.
+fn collect_files(
+    root: &Path,
+    dir: &Path,
+    found: &mut Vec<(String, PathBuf, u64)>,
+) -> Result<(), HasherError> {
+    let entries = fs::read_dir(dir).map_err(|e| HasherError::io(dir, e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| HasherError::io(dir, e))?;
+        let full_path = entry.path();
+        let file_type = entry
+            .file_type()
+            .map_err(|e| HasherError::io(&full_path, e))?;
+        if file_type.is_dir() {
+            collect_files(root, &full_path, found)?;
+        } else if file_type.is_file() {
+            let size = entry
+                .metadata()
+                .map_err(|e| HasherError::io(&full_path, e))?
+                .len();
+            found.push((relative_path(root, &full_path)?, full_path, size));
+        }
+    }
+    Ok(())
+}
+
+fn relative_path(root: &Path, path: &Path) -> Result<String, HasherError> {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let components = relative
+        .components()
+        .map(|component| {
+            component.as_os_str().to_str().ok_or_else(|| {
+                HasherError::InvalidInput(format!(
+                    "File name is not valid UTF-8: '{}'",
+                    path.display()
+                ))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(components.join("/"))
+}
+
+/// A parsed manifest together with its tag. The file list is untrusted until `verify` succeeds.
+#[derive(Debug, Clone)]
+pub struct SignedManifest {
+    manifest: DirectoryManifest,
+    tag: Vec<u8>,
+}
+
+impl SignedManifest {
+    /// Parses manifest text produced by `DirectoryManifest::sign`.
+    /// Raises InvalidDigest if it is not a well-formed manifest.
+    pub fn parse(text: &str) -> Result<Self, HasherError> {
+        let malformed = |reason: String| {
+            HasherError::InvalidDigest(format!("Malformed directory manifest: {}", reason))
+        };
+        let mut lines = text.lines();
+        if lines.next() != Some(MANIFEST_HEADER) {
+            return Err(malformed(format!("expected '{}' header", MANIFEST_HEADER)));
+        }
+        let mut files = Vec::new();
+        let mut tag = None;
+        for (index, line) in lines.enumerate() {
+            let number = index + 2;
+            if tag.is_some() {
+                return Err(malformed(format!(
+                    "unexpected content after the tag on line {}",
+                    number
+                )));
+            }
+            if let Some(tag_hex) = line.strip_prefix(TAG_PREFIX) {
+                tag = Some(hex::decode(tag_hex).map_err(|e| malformed(format!("bad tag: {}", e)))?);
+                continue;
+            }
+            let parsed = line.split_once(' ').and_then(|(size, rest)| {
+                let (digest, path) = rest.split_once(' ')?;
+                Some((
+                    size.parse::<u64>().ok()?,
+                    Digest::from_hex(Algorithm::Sha256, digest).ok()?,
+                    unescape_name(path)?,
+                ))
+            });
+            let Some((size, digest, path)) = parsed.filter(|(_, _, path)| !path.is_empty()) else {
+                return Err(malformed(format!("bad entry on line {}", number)));
+            };
+            if files
+                .last()
+                .is_some_and(|last: &ManifestFile| last.path >= path)
+            {
+                return Err(malformed(format!(
+                    "entries not sorted by path on line {}",
+                    number
+                )));
+            }
+            files.push(ManifestFile { path, size, digest });
+        }
+        let tag = tag.ok_or_else(|| malformed("missing tag line".to_string()))?;
+        Ok(SignedManifest {
+            manifest: DirectoryManifest { files },
+            tag,
+        })
+    }
+
+    /// Verifies the tag in constant time. Returns false if the manifest was not signed with `secret_key`
+    /// or was edited since.
+    pub fn verify(&self, secret_key: &[u8]) -> Result<bool, HasherError> {
+        Algorithm::Sha256.verify_hmac(secret_key, self.manifest.body().as_bytes(), &self.tag)
+    }
+
+    /// The listed files. Only trust them after `verify` has returned true.
+    pub fn manifest(&self) -> &DirectoryManifest {
+        &self.manifest
+    }
+}
+
+/// Verifies a signed manifest and compares it against the current contents of `root`.
+/// Returns `None` if the manifest's tag does not verify, otherwise the differences (empty if unchanged).
+pub fn verify_directory(
+    text: &str,
+    secret_key: &[u8],
+    root: impl AsRef<Path>,
+) -> Result<Option<ManifestDiff>, HasherError> {
+    let signed = SignedManifest::parse(text)?;
+    if !signed.verify(secret_key)? {
+        return Ok(None);
+    }
+    Ok(Some(
+        signed
+            .manifest()
+            .diff(&DirectoryManifest::from_directory(root)?),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::fs;
+
+    const KEY: &[u8] = &[b'k'; 32];
+
+    /// A directory with a nested file, an empty file and a file whose name needs escaping.
+    fn documents(name: &str) -> TempDir {
+        let dir = TempDir::new(name);
+        dir.write("contracts/a.pdf", b"hello");
+        dir.write("empty", b"");
+        dir.write("notes.txt", b"hello world");
+        dir.write("odd\\name.txt", b"odd");
+        dir
+    }
+
+    fn sign(dir: &TempDir) -> String {
+        DirectoryManifest::from_directory(dir.path())
+            .unwrap()
+            .sign(KEY)
+            .unwrap()
+    }
+
+    fn verify(text: &str, dir: &TempDir) -> Option<ManifestDiff> {
+        verify_directory(text, KEY, dir.path()).unwrap()
+    }
+
+    #[test]
+    fn manifest_lists_files_sorted_by_path_then_the_tag() {
+        let dir = documents("directory-format");
+        let text = sign(&dir);
+        let odd = crate::sha256(b"odd").to_hex();
+        let body = format!(
+            "{}\n\
+             5 2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824 contracts/a.pdf\n\
+             0 e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 empty\n\
+             11 b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9 notes.txt\n\
+             3 {} odd\\\\name.txt\n",
+            MANIFEST_HEADER, odd
+        );
+        let tag = crate::hmac_sha256(KEY, body.as_bytes()).unwrap();
+        assert_eq!(text, format!("{}hmac-sha256 {}\n", body, tag.to_hex()));
+
+        let signed = SignedManifest::parse(&text).unwrap();
+        assert!(signed.verify(KEY).unwrap());
+        assert_eq!(signed.manifest().files()[3].path, "odd\\name.txt");
+        assert_eq!(verify(&text, &dir), Some(ManifestDiff::default()));
+    }
+
+    #[test]
+    fn changes_to_the_directory_are_reported() {
+        let dir = documents("directory-changes");
+        let text = sign(&dir);
+        dir.write("contracts/b.pdf", b"new");
+        fs::remove_file(dir.path().join("empty")).unwrap();
+        fs::rename(
+            dir.path().join("contracts/a.pdf"),
+            dir.path().join("contracts/renamed.pdf"),
+        )
+        .unwrap();
+        dir.write("notes.txt", b"hello world!");
+        let diff = verify(&text, &dir).unwrap();
+        assert_eq!(diff.added, ["contracts/b.pdf"]);
+        assert_eq!(diff.removed, ["empty"]);
+        assert_eq!(
+            diff.renamed,
+            [(
+                "contracts/a.pdf".to_string(),
+                "contracts/renamed.pdf".to_string()
+            )]
+        );
+        assert_eq!(diff.modified, ["notes.txt"]);
+        assert!(!diff.is_empty());
+    }
+
+    #[test]
+    fn tampered_manifest_does_not_verify() {
+        let dir = documents("directory-tampered");
+        let text = sign(&dir);
+        let mut tag_flipped = text.clone().into_bytes();
+        let last_hex = tag_flipped.len() - 2;
+        tag_flipped[last_hex] = if tag_flipped[last_hex] == b'0' {
+            b'1'
+        } else {
+            b'0'
+        };
+        let tampered = [
+            String::from_utf8(tag_flipped).unwrap(),
+            text.replace("\n11 ", "\n12 "),
+            text.replace("notes.txt", "notez.txt"),
+        ];
+        for tampered in tampered {
+            assert_ne!(tampered, text);
+            assert_eq!(verify(&tampered, &dir), None, "{}", tampered);
+        }
+        assert_eq!(
+            verify_directory(&text, &[b'j'; 32], dir.path()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn manifest_must_be_canonical() {
+        let dir = documents("directory-canonical");
+        let text = sign(&dir);
+        let mut lines: Vec<&str> = text.lines().collect();
+        lines.swap(1, 2);
+        let reordered = lines.join("\n");
+        assert!(matches!(
+            SignedManifest::parse(&reordered),
+            Err(HasherError::InvalidDigest(_))
+        ));
+        let duplicated = text.replacen(
+            "\n0 ",
+            &format!("\n{}\n0 ", text.lines().nth(1).unwrap()),
+            1,
+        );
+        assert!(SignedManifest::parse(&duplicated).is_err());
+        let malformed = [
+            text.replacen(MANIFEST_HEADER, "hashsure-directory-manifest v2", 1),
+            text.replace("hmac-sha256 ", "hmac-sha256 zz"),
+            format!("{}extra\n", text),
+            text.lines().take(5).collect::<Vec<_>>().join("\n"),
+        ];
+        for text in malformed {
+            assert!(SignedManifest::parse(&text).is_err(), "{}", text);
+        }
+    }
+}
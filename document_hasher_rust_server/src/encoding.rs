@@ -0,0 +1,8 @@
+//! Helpers for the fixed binary encodings that are signed or MACed (receipts, records, log entries,
+//! canonical PDF objects).
+
+/// Appends `field` preceded by its length as 4 big-endian bytes.
+pub(crate) fn push_field(out: &mut Vec<u8>, field: &[u8]) {
+    out.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    out.extend_from_slice(field);
+}
@@ -0,0 +1,85 @@
+use crate::algorithm::UnknownAlgorithm;
+use crate::keyring::KeyringError;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Errors raised by the hasher. With the `python` feature, each variant maps to a Python exception
+/// class registered on the module (see `HasherError`, `InvalidKeyError`, ... in `python.rs`).
+#[derive(Debug)]
+pub enum HasherError {
+    /// An HMAC key was rejected.
+    InvalidKey(String),
+    /// A digest, tag or proof node was not valid hex or had the wrong length.
+    InvalidDigest(String),
+    /// An algorithm name was not recognised.
+    UnsupportedAlgorithm(String),
+    /// Reading a file failed.
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// An argument was out of range (e.g. a zero chunk size).
+    InvalidInput(String),
+}
+
+impl HasherError {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        HasherError::Io {
+            path: Some(path.into()),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for HasherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HasherError::InvalidKey(e)
+            | HasherError::InvalidDigest(e)
+            | HasherError::UnsupportedAlgorithm(e)
+            | HasherError::InvalidInput(e) => f.write_str(e),
+            HasherError::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "{}: '{}'", source, path.display()),
+            HasherError::Io { path: None, source } => write!(f, "{}", source),
+        }
+    }
+}
+
+impl std::error::Error for HasherError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HasherError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HasherError {
+    fn from(source: io::Error) -> Self {
+        HasherError::Io { path: None, source }
+    }
+}
+
+impl From<UnknownAlgorithm> for HasherError {
+    fn from(e: UnknownAlgorithm) -> Self {
+        HasherError::UnsupportedAlgorithm(e.to_string())
+    }
+}
+
+impl From<hmac::digest::InvalidLength> for HasherError {
+    fn from(e: hmac::digest::InvalidLength) -> Self {
+        HasherError::InvalidKey(format!("HMAC key error: {}", e))
+    }
+}
+
+impl From<KeyringError> for HasherError {
+    fn from(e: KeyringError) -> Self {
+        match e {
+            KeyringError::MalformedTag(_) => HasherError::InvalidDigest(e.to_string()),
+            _ => HasherError::InvalidKey(e.to_string()),
+        }
+    }
+}
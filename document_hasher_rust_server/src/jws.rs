@@ -0,0 +1,366 @@
+//! JWS (RFC 7515) compact signatures over a document's digest, with a detached, unencoded payload
+//! (RFC 7797), for consumers that only speak JOSE.
+//!
+//! The payload is the document's hex digest, signed as-is (`"b64": false`, listed in `"crit"`) and left out
+//! of the serialization: `BASE64URL(header)..BASE64URL(signature)`. Supported algorithms are `HS256` (with
+//! the server's HMAC key), `EdDSA` (Ed25519) and `ES256` (ECDSA P-256).
+//!
+//! On verification the algorithm is fixed by the key the caller supplies, never by the header:
+//! `alg: none` and any header `alg` that doesn't match the key are rejected, which rules out
+//! algorithm-confusion attacks such as verifying an HS256 token with a public key as the HMAC secret.
+
+use crate::ecdsa_p256::{EcdsaP256PrivateKey, EcdsaP256PublicKey};
+use crate::ed25519::{Ed25519PrivateKey, Ed25519PublicKey};
+use crate::{Algorithm, Digest, HasherError};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use serde_json::Value;
+
+/// A JWS signature algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JwsAlgorithm {
+    Hs256,
+    EdDsa,
+    Es256,
+}
+
+impl JwsAlgorithm {
+    /// The `alg` header value.
+    pub fn name(self) -> &'static str {
+        match self {
+            JwsAlgorithm::Hs256 => "HS256",
+            JwsAlgorithm::EdDsa => "EdDSA",
+            JwsAlgorithm::Es256 => "ES256",
+        }
+    }
+}
+
+/// A key to sign with; the variant determines the algorithm.
+#[derive(Clone, Copy)]
+pub enum JwsSigningKey<'a> {
+    Hs256(&'a [u8]),
+    EdDsa(&'a Ed25519PrivateKey),
+    Es256(&'a EcdsaP256PrivateKey),
+}
+
+/// A key to verify with; the variant determines the only algorithm accepted.
+#[derive(Clone, Copy)]
+pub enum JwsVerifyingKey<'a> {
+    Hs256(&'a [u8]),
+    EdDsa(&'a Ed25519PublicKey),
+    Es256(&'a EcdsaP256PublicKey),
+}
+
+impl JwsSigningKey<'_> {
+    pub fn algorithm(&self) -> JwsAlgorithm {
+        match self {
+            JwsSigningKey::Hs256(_) => JwsAlgorithm::Hs256,
+            JwsSigningKey::EdDsa(_) => JwsAlgorithm::EdDsa,
+            JwsSigningKey::Es256(_) => JwsAlgorithm::Es256,
+        }
+    }
+}
+
+impl JwsVerifyingKey<'_> {
+    pub fn algorithm(&self) -> JwsAlgorithm {
+        match self {
+            JwsVerifyingKey::Hs256(_) => JwsAlgorithm::Hs256,
+            JwsVerifyingKey::EdDsa(_) => JwsAlgorithm::EdDsa,
+            JwsVerifyingKey::Es256(_) => JwsAlgorithm::Es256,
+        }
+    }
+}
+
+fn malformed(reason: impl std::fmt::Display) -> HasherError {
+    HasherError::InvalidDigest(format!("Malformed JWS: {}", reason))
+}
+
+/// The JWS signing input for an unencoded payload: `ASCII(BASE64URL(header)) || '.' || payload`.
+fn signing_input(encoded_header: &str, digest: &Digest) -> Vec<u8> {
+    format!("{}.{}", encoded_header, digest.to_hex()).into_bytes()
+}
+
+/// Signs `digest` and returns the detached compact serialization. `key_id`, if given, is set as `kid`.
+pub fn sign_detached(
+    digest: &Digest,
+    key: JwsSigningKey<'_>,
+    key_id: Option<&str>,
+) -> Result<String, HasherError> {
+    let mut header =
+        serde_json::json!({ "alg": key.algorithm().name(), "b64": false, "crit": ["b64"] });
+    if let Some(key_id) = key_id {
+        header["kid"] = Value::from(key_id);
+    }
+    let encoded_header = BASE64URL.encode(header.to_string());
+    let input = signing_input(&encoded_header, digest);
+    let signature = match key {
+        JwsSigningKey::Hs256(secret_key) => {
+            Algorithm::Sha256.hmac(secret_key, &input)?.into_bytes()
+        }
+        JwsSigningKey::EdDsa(private_key) => private_key.sign(&input).to_vec(),
+        JwsSigningKey::Es256(private_key) => private_key.sign(&input).to_vec(),
+    };
+    Ok(format!(
+        "{}..{}",
+        encoded_header,
+        BASE64URL.encode(signature)
+    ))
+}
+
+/// Verifies a detached JWS over `digest` with `key`. Returns false for an invalid signature; raises
+/// InvalidKey if the header's `alg` is `none` or doesn't match the key, and InvalidDigest if the JWS is malformed.
+pub fn verify_detached(
+    jws: &str,
+    digest: &Digest,
+    key: JwsVerifyingKey<'_>,
+) -> Result<bool, HasherError> {
+    let mut parts = jws.split('.');
+    let (Some(encoded_header), Some(payload), Some(encoded_signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(malformed("expected three '.'-separated parts"));
+    };
+    if !payload.is_empty() {
+        return Err(malformed("payload must be detached"));
+    }
+    let header = BASE64URL
+        .decode(encoded_header)
+        .map_err(|e| malformed(format!("header: {}", e)))?;
+    let header: Value =
+        serde_json::from_slice(&header).map_err(|e| malformed(format!("header: {}", e)))?;
+    let header = header
+        .as_object()
+        .ok_or_else(|| malformed("header is not a JSON object"))?;
+
+    let alg = header
+        .get("alg")
+        .and_then(Value::as_str)
+        .ok_or_else(|| malformed("missing 'alg'"))?;
+    if alg.eq_ignore_ascii_case("none") {
+        return Err(HasherError::InvalidKey(
+            "JWS alg 'none' is not accepted".to_string(),
+        ));
+    }
+    if alg != key.algorithm().name() {
+        return Err(HasherError::InvalidKey(format!(
+            "JWS alg '{}' does not match the {} key",
+            alg,
+            key.algorithm().name()
+        )));
+    }
+    if header.get("b64") != Some(&Value::Bool(false)) {
+        return Err(malformed("'b64' must be false"));
+    }
+    let crit = header
+        .get("crit")
+        .and_then(Value::as_array)
+        .ok_or_else(|| malformed("'crit' must list 'b64'"))?;
+    if crit.is_empty() || crit.iter().any(|name| name != "b64") {
+        return Err(malformed("'crit' must list only 'b64'"));
+    }
+
+    let signature = BASE64URL
+        .decode(encoded_signature)
+        .map_err(|e| malformed(format!("signature: {}", e)))?;
+    let input = signing_input(encoded_header, digest);
+    match key {
+        JwsVerifyingKey::Hs256(secret_key) => {
+            Algorithm::Sha256.verify_hmac(secret_key, &input, &signature)
+        }
+        JwsVerifyingKey::EdDsa(public_key) => public_key.verify(&input, &signature),
+        JwsVerifyingKey::Es256(public_key) => public_key.verify(&input, &signature),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HMAC_KEY: &[u8] = &[b'k'; 32];
+
+    fn digest() -> Digest {
+        crate::sha256(b"contract")
+    }
+
+    fn ed25519_key() -> Ed25519PrivateKey {
+        Ed25519PrivateKey::from_raw(&[7; 32]).unwrap()
+    }
+
+    fn p256_key() -> EcdsaP256PrivateKey {
+        EcdsaP256PrivateKey::from_raw(&[7; 32]).unwrap()
+    }
+
+    /// A detached JWS with an arbitrary header, signed with `key` over the given header as encoded.
+    fn with_header(header: Value, key: JwsSigningKey<'_>) -> String {
+        let encoded_header = BASE64URL.encode(header.to_string());
+        let input = signing_input(&encoded_header, &digest());
+        let signature = match key {
+            JwsSigningKey::Hs256(secret_key) => Algorithm::Sha256
+                .hmac(secret_key, &input)
+                .unwrap()
+                .into_bytes(),
+            JwsSigningKey::EdDsa(private_key) => private_key.sign(&input).to_vec(),
+            JwsSigningKey::Es256(private_key) => private_key.sign(&input).to_vec(),
+        };
+        format!("{}..{}", encoded_header, BASE64URL.encode(signature))
+    }
+
+    /// Flips a bit in the first byte of the signature.
+    fn tamper_signature(jws: &str) -> String {
+        let (header, signature) = jws.split_once("..").unwrap();
+        let mut signature = BASE64URL.decode(signature).unwrap();
+        signature[0] ^= 1;
+        format!("{}..{}", header, BASE64URL.encode(signature))
+    }
+
+    #[test]
+    fn every_algorithm_signs_and_verifies_and_rejects_tampering() {
+        let ed25519_key = ed25519_key();
+        let p256_key = p256_key();
+        let (ed25519_public, p256_public) = (ed25519_key.public_key(), p256_key.public_key());
+        let keys = [
+            (
+                JwsSigningKey::Hs256(HMAC_KEY),
+                JwsVerifyingKey::Hs256(HMAC_KEY),
+            ),
+            (
+                JwsSigningKey::EdDsa(&ed25519_key),
+                JwsVerifyingKey::EdDsa(&ed25519_public),
+            ),
+            (
+                JwsSigningKey::Es256(&p256_key),
+                JwsVerifyingKey::Es256(&p256_public),
+            ),
+        ];
+        for (signing_key, verifying_key) in keys {
+            let name = signing_key.algorithm().name();
+            let jws = sign_detached(&digest(), signing_key, Some("2024-01")).unwrap();
+            let header: Value =
+                serde_json::from_slice(&BASE64URL.decode(jws.split('.').next().unwrap()).unwrap())
+                    .unwrap();
+            assert_eq!(
+                header,
+                serde_json::json!({ "alg": name, "b64": false, "crit": ["b64"], "kid": "2024-01" })
+            );
+            assert!(
+                verify_detached(&jws, &digest(), verifying_key).unwrap(),
+                "{}",
+                name
+            );
+            let other_document = crate::sha256(b"contract2");
+            assert!(
+                !verify_detached(&jws, &other_document, verifying_key).unwrap(),
+                "{}",
+                name
+            );
+            let tampered = tamper_signature(&jws);
+            assert!(
+                !verify_detached(&tampered, &digest(), verifying_key).unwrap(),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn alg_none_is_rejected() {
+        let ed25519_public = ed25519_key().public_key();
+        for alg in ["none", "None", "NONE"] {
+            let header = serde_json::json!({ "alg": alg, "b64": false, "crit": ["b64"] });
+            let jws = format!("{}..", BASE64URL.encode(header.to_string()));
+            for key in [
+                JwsVerifyingKey::Hs256(HMAC_KEY),
+                JwsVerifyingKey::EdDsa(&ed25519_public),
+            ] {
+                assert!(matches!(
+                    verify_detached(&jws, &digest(), key),
+                    Err(HasherError::InvalidKey(_))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn header_alg_must_match_the_key() {
+        // The classic confusion: an HS256 token keyed with the bytes of the verifier's public key.
+        let ed25519_public = ed25519_key().public_key();
+        let p256_public = p256_key().public_key();
+        let forged = [
+            (
+                sign_detached(
+                    &digest(),
+                    JwsSigningKey::Hs256(&ed25519_public.to_raw()),
+                    None,
+                ),
+                JwsVerifyingKey::EdDsa(&ed25519_public),
+            ),
+            (
+                sign_detached(&digest(), JwsSigningKey::Hs256(&p256_public.to_raw()), None),
+                JwsVerifyingKey::Es256(&p256_public),
+            ),
+        ];
+        for (jws, key) in forged {
+            assert!(matches!(
+                verify_detached(&jws.unwrap(), &digest(), key),
+                Err(HasherError::InvalidKey(_))
+            ));
+        }
+        let ed25519_key = ed25519_key();
+        let eddsa = sign_detached(&digest(), JwsSigningKey::EdDsa(&ed25519_key), None).unwrap();
+        assert!(matches!(
+            verify_detached(&eddsa, &digest(), JwsVerifyingKey::Es256(&p256_public)),
+            Err(HasherError::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn unencoded_payload_headers_are_required() {
+        let headers = [
+            serde_json::json!({ "alg": "HS256", "crit": ["b64"] }),
+            serde_json::json!({ "alg": "HS256", "b64": true, "crit": ["b64"] }),
+            serde_json::json!({ "alg": "HS256", "b64": false }),
+            serde_json::json!({ "alg": "HS256", "b64": false, "crit": [] }),
+            serde_json::json!({ "alg": "HS256", "b64": false, "crit": ["b64", "exp"] }),
+            serde_json::json!({ "b64": false, "crit": ["b64"] }),
+        ];
+        for header in headers {
+            let jws = with_header(header.clone(), JwsSigningKey::Hs256(HMAC_KEY));
+            assert!(
+                matches!(
+                    verify_detached(&jws, &digest(), JwsVerifyingKey::Hs256(HMAC_KEY)),
+                    Err(HasherError::InvalidDigest(_))
+                ),
+                "{}",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_serializations_are_rejected() {
+        let jws = sign_detached(&digest(), JwsSigningKey::Hs256(HMAC_KEY), None).unwrap();
+        let (header, signature) = jws.split_once("..").unwrap();
+        let malformed = [
+            format!(
+                "{}.{}.{}",
+                header,
+                BASE64URL.encode(digest().to_hex()),
+                signature
+            ),
+            format!("{}..{}.", header, signature),
+            format!("{}.{}", header, signature),
+            format!("{}..{}", &header[1..], signature),
+            format!("{}..!", header),
+        ];
+        for jws in malformed {
+            assert!(
+                matches!(
+                    verify_detached(&jws, &digest(), JwsVerifyingKey::Hs256(HMAC_KEY)),
+                    Err(HasherError::InvalidDigest(_))
+                ),
+                "{}",
+                jws
+            );
+        }
+    }
+}
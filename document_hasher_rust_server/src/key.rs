@@ -0,0 +1,167 @@
+//! Validated HMAC secret keys held in memory that is wiped on drop.
+
+use crate::error::HasherError;
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// Keys shorter than this are rejected by default: HMAC keys should be at least
+/// as long as the hash output (32 bytes for SHA256).
+pub const DEFAULT_MIN_KEY_LENGTH: usize = 32;
+
+/// An HMAC secret key that has passed the strength checks in `HmacKey::new`.
+/// The key bytes are zeroized when the key is dropped and never appear in `Debug` output.
+#[derive(Clone)]
+pub struct HmacKey {
+    bytes: Zeroizing<Vec<u8>>,
+}
+
+impl HmacKey {
+    /// Validates `bytes` as an HMAC key: it must be at least `min_length` bytes long (and never empty),
+    /// and, if `min_entropy_bits` is given, its estimated entropy must reach that many bits.
+    pub fn new(
+        bytes: &[u8],
+        min_length: usize,
+        min_entropy_bits: Option<f64>,
+    ) -> Result<Self, HasherError> {
+        if bytes.is_empty() || bytes.len() < min_length {
+            return Err(HasherError::InvalidKey(format!(
+                "HMAC key is too short: {} bytes, at least {} required",
+                bytes.len(),
+                min_length.max(1)
+            )));
+        }
+        if let Some(min_entropy_bits) = min_entropy_bits {
+            let estimated = estimate_entropy_bits(bytes);
+            if estimated < min_entropy_bits {
+                return Err(HasherError::InvalidKey(format!(
+                    "HMAC key is too weak: estimated {:.1} bits of entropy, at least {:.1} required",
+                    estimated, min_entropy_bits
+                )));
+            }
+        }
+        Ok(HmacKey {
+            bytes: Zeroizing::new(bytes.to_vec()),
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn estimated_entropy_bits(&self) -> f64 {
+        estimate_entropy_bits(&self.bytes)
+    }
+}
+
+impl fmt::Debug for HmacKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HmacKey(length={})", self.len())
+    }
+}
+
+/// Estimates the entropy of a key in bits as its length times the Shannon entropy of its byte distribution.
+/// This is only a heuristic: it catches repeated or low-variety keys (e.g. `"aaaa..."` or a short word
+/// repeated), but cannot tell a random key from a predictable one with varied bytes.
+pub fn estimate_entropy_bits(bytes: &[u8]) -> f64 {
+    if bytes.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0usize; 256];
+    for &b in bytes {
+        counts[b as usize] += 1;
+    }
+    let len = bytes.len() as f64;
+    let per_byte: f64 = counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            p * (1.0 / p).log2()
+        })
+        .sum();
+    per_byte * len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 32 bytes from a CSPRNG.
+    const RANDOM_KEY: &str = "28ab248ac4b0f31095b1e3242e52497404d3090c2f8e32ba019fe700c6d84cf9";
+
+    #[test]
+    fn keys_shorter_than_the_minimum_are_rejected() {
+        let key = [0x5au8; DEFAULT_MIN_KEY_LENGTH];
+        assert!(HmacKey::new(&key, DEFAULT_MIN_KEY_LENGTH, None).is_ok());
+        assert!(matches!(
+            HmacKey::new(
+                &key[..DEFAULT_MIN_KEY_LENGTH - 1],
+                DEFAULT_MIN_KEY_LENGTH,
+                None
+            ),
+            Err(HasherError::InvalidKey(_))
+        ));
+        assert_eq!(HmacKey::new(&key[..4], 4, None).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn empty_keys_are_rejected_even_without_a_minimum() {
+        for min_length in [0, 1, DEFAULT_MIN_KEY_LENGTH] {
+            assert!(matches!(
+                HmacKey::new(b"", min_length, None),
+                Err(HasherError::InvalidKey(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn entropy_threshold_rejects_repeated_bytes_and_accepts_a_random_key() {
+        let repeated = [b'a'; 64];
+        assert_eq!(estimate_entropy_bits(&repeated), 0.0);
+        assert!(matches!(
+            HmacKey::new(&repeated, DEFAULT_MIN_KEY_LENGTH, Some(128.0)),
+            Err(HasherError::InvalidKey(_))
+        ));
+        // Without a threshold only the length is checked.
+        assert!(HmacKey::new(&repeated, DEFAULT_MIN_KEY_LENGTH, None).is_ok());
+
+        let random = hex::decode(RANDOM_KEY).unwrap();
+        let key = HmacKey::new(&random, DEFAULT_MIN_KEY_LENGTH, Some(128.0)).unwrap();
+        assert!(key.estimated_entropy_bits() >= 128.0);
+        assert!(matches!(
+            HmacKey::new(
+                &random,
+                DEFAULT_MIN_KEY_LENGTH,
+                Some(key.estimated_entropy_bits() + 1.0)
+            ),
+            Err(HasherError::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn entropy_estimate_counts_distinct_bytes() {
+        assert_eq!(estimate_entropy_bits(b""), 0.0);
+        assert_eq!(estimate_entropy_bits(b"abab"), 4.0);
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(estimate_entropy_bits(&all_bytes), 256.0 * 8.0);
+    }
+
+    #[test]
+    fn debug_output_redacts_the_key() {
+        let random = hex::decode(RANDOM_KEY).unwrap();
+        let key = HmacKey::new(&random, DEFAULT_MIN_KEY_LENGTH, None).unwrap();
+        let debug = format!("{:?}", key);
+        assert_eq!(debug, "HmacKey(length=32)");
+        assert!(!debug.contains(RANDOM_KEY));
+        assert!(!debug.contains(&format!("{:?}", random)));
+        assert!(!debug.contains(&format!("{}, {}", random[0], random[1])));
+    }
+}
@@ -0,0 +1,254 @@
+//! Versioned HMAC-SHA256 keys, so the server secret can be rotated without invalidating stored tags.
+//! Tags carry the id of the key that produced them, e.g. `v2:<hex>`.
+
+use crate::Algorithm;
+use crate::key::HmacKey;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Separates the key id from the hex tag.
+pub const TAG_SEPARATOR: char = ':';
+
+/// Keyring tags are HMAC-SHA256.
+const ALGORITHM: Algorithm = Algorithm::Sha256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyringError {
+    InvalidKeyId(String),
+    DuplicateKeyId(String),
+    UnknownKeyId(String),
+    NoPrimaryKey,
+    InvalidKey(String),
+    MalformedTag(String),
+}
+
+impl fmt::Display for KeyringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyringError::InvalidKeyId(id) => {
+                write!(
+                    f,
+                    "Invalid key id '{}': use only letters, digits, '.', '-' and '_'",
+                    id
+                )
+            }
+            KeyringError::DuplicateKeyId(id) => {
+                write!(f, "Key id '{}' is already in the keyring", id)
+            }
+            KeyringError::UnknownKeyId(id) => write!(f, "Key id '{}' is not in the keyring", id),
+            KeyringError::NoPrimaryKey => write!(f, "The keyring has no primary key"),
+            KeyringError::InvalidKey(e) => write!(f, "HMAC key error: {}", e),
+            KeyringError::MalformedTag(e) => write!(f, "Malformed HMAC tag: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for KeyringError {}
+
+/// A set of HMAC-SHA256 keys indexed by id, one of which is the primary (signing) key.
+#[derive(Default)]
+pub struct Keyring {
+    keys: BTreeMap<String, HmacKey>,
+    primary: Option<String>,
+    legacy: Option<String>,
+}
+
+fn validate_key_id(key_id: &str) -> Result<(), KeyringError> {
+    let valid = !key_id.is_empty()
+        && key_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+    if valid {
+        Ok(())
+    } else {
+        Err(KeyringError::InvalidKeyId(key_id.to_string()))
+    }
+}
+
+impl Keyring {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a key under `key_id`. Key ids must be unique; rotate by adding a new id rather than replacing one.
+    pub fn add_key(&mut self, key_id: &str, key: HmacKey) -> Result<(), KeyringError> {
+        validate_key_id(key_id)?;
+        if self.keys.contains_key(key_id) {
+            return Err(KeyringError::DuplicateKeyId(key_id.to_string()));
+        }
+        self.keys.insert(key_id.to_string(), key);
+        Ok(())
+    }
+
+    /// Makes `key_id` the key used for new tags.
+    pub fn set_primary(&mut self, key_id: &str) -> Result<(), KeyringError> {
+        self.require_key(key_id)?;
+        self.primary = Some(key_id.to_string());
+        Ok(())
+    }
+
+    /// Sets the key that unversioned (bare hex) tags were produced with,
+    /// so tags issued before key ids were introduced still verify.
+    pub fn set_legacy(&mut self, key_id: &str) -> Result<(), KeyringError> {
+        self.require_key(key_id)?;
+        self.legacy = Some(key_id.to_string());
+        Ok(())
+    }
+
+    pub fn primary_key_id(&self) -> Option<&str> {
+        self.primary.as_deref()
+    }
+
+    pub fn legacy_key_id(&self) -> Option<&str> {
+        self.legacy.as_deref()
+    }
+
+    pub fn key_ids(&self) -> impl Iterator<Item = &str> {
+        self.keys.keys().map(String::as_str)
+    }
+
+    fn require_key(&self, key_id: &str) -> Result<&[u8], KeyringError> {
+        self.keys
+            .get(key_id)
+            .map(HmacKey::as_bytes)
+            .ok_or_else(|| KeyringError::UnknownKeyId(key_id.to_string()))
+    }
+
+    /// Tags `message` with the primary key, returning `<key_id>:<hex tag>`.
+    pub fn sign(&self, message: &[u8]) -> Result<String, KeyringError> {
+        let key_id = self.primary.as_deref().ok_or(KeyringError::NoPrimaryKey)?;
+        let tag = ALGORITHM
+            .hmac(self.require_key(key_id)?, message)
+            .map_err(|e| KeyringError::InvalidKey(e.to_string()))?;
+        Ok(format!("{}{}{}", key_id, TAG_SEPARATOR, tag.to_hex()))
+    }
+
+    /// Splits a tag into the id of the key that produced it and the hex tag.
+    /// Unversioned tags are attributed to the legacy key, if one is set.
+    fn split_tag<'a>(&'a self, tag: &'a str) -> (Option<&'a str>, &'a str) {
+        match tag.split_once(TAG_SEPARATOR) {
+            Some((key_id, tag_hex)) => (Some(key_id), tag_hex),
+            None => (self.legacy.as_deref(), tag),
+        }
+    }
+
+    /// Verifies `tag` over `message` in constant time, using whichever key the tag names.
+    /// Tags naming a key that is not in the keyring do not verify.
+    pub fn verify(&self, message: &[u8], tag: &str) -> Result<bool, KeyringError> {
+        let (key_id, tag_hex) = self.split_tag(tag);
+        let tag_bytes =
+            hex::decode(tag_hex).map_err(|e| KeyringError::MalformedTag(e.to_string()))?;
+        // Checked before the key lookup so that a malformed tag is an error even under an unknown key id.
+        if tag_bytes.len() != ALGORITHM.output_size() {
+            return Err(KeyringError::MalformedTag(format!(
+                "expected {} bytes, got {}",
+                ALGORITHM.output_size(),
+                tag_bytes.len()
+            )));
+        }
+        let Some(key) = key_id.and_then(|id| self.keys.get(id)) else {
+            return Ok(false);
+        };
+        ALGORITHM
+            .verify_hmac(key.as_bytes(), message, &tag_bytes)
+            .map_err(|e| KeyringError::InvalidKey(e.to_string()))
+    }
+
+    /// Whether `tag` was produced by a key other than the current primary and should be re-signed.
+    pub fn needs_resign(&self, tag: &str) -> bool {
+        let key_id = tag.split_once(TAG_SEPARATOR).map(|(key_id, _)| key_id);
+        key_id.is_none() || key_id != self.primary.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &[u8] = b"e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    fn key(byte: u8) -> HmacKey {
+        HmacKey::new(&[byte; 32], 32, None).unwrap()
+    }
+
+    fn keyring() -> Keyring {
+        let mut keyring = Keyring::new();
+        keyring.add_key("v1", key(b'1')).unwrap();
+        keyring.set_primary("v1").unwrap();
+        keyring
+    }
+
+    #[test]
+    fn sign_prefixes_the_primary_key_id_and_verifies() {
+        let keyring = keyring();
+        let tag = keyring.sign(MESSAGE).unwrap();
+        let expected = crate::hmac_sha256(&[b'1'; 32], MESSAGE).unwrap();
+        assert_eq!(tag, format!("v1:{}", expected.to_hex()));
+        assert!(keyring.verify(MESSAGE, &tag).unwrap());
+        assert!(!keyring.verify(b"another message", &tag).unwrap());
+        assert!(!keyring.needs_resign(&tag));
+        assert_eq!(
+            Keyring::new().sign(MESSAGE),
+            Err(KeyringError::NoPrimaryKey)
+        );
+    }
+
+    #[test]
+    fn old_tags_verify_after_rotation_and_need_resigning() {
+        let mut keyring = keyring();
+        let old_tag = keyring.sign(MESSAGE).unwrap();
+        keyring.add_key("v2", key(b'2')).unwrap();
+        keyring.set_primary("v2").unwrap();
+        let new_tag = keyring.sign(MESSAGE).unwrap();
+        assert!(new_tag.starts_with("v2:"));
+        assert!(keyring.verify(MESSAGE, &old_tag).unwrap());
+        assert!(keyring.verify(MESSAGE, &new_tag).unwrap());
+        assert!(keyring.needs_resign(&old_tag));
+        assert!(!keyring.needs_resign(&new_tag));
+        // Swapping the key id onto the other key's tag must not verify.
+        let relabelled = old_tag.replacen("v1", "v2", 1);
+        assert!(!keyring.verify(MESSAGE, &relabelled).unwrap());
+        assert_eq!(
+            keyring.add_key("v2", key(b'3')),
+            Err(KeyringError::DuplicateKeyId("v2".to_string()))
+        );
+    }
+
+    #[test]
+    fn unversioned_tags_verify_only_with_a_legacy_key() {
+        let mut keyring = keyring();
+        let bare = crate::hmac_sha256(&[b'1'; 32], MESSAGE).unwrap().to_hex();
+        assert!(!keyring.verify(MESSAGE, &bare).unwrap());
+        keyring.set_legacy("v1").unwrap();
+        assert!(keyring.verify(MESSAGE, &bare).unwrap());
+        assert!(keyring.needs_resign(&bare));
+    }
+
+    #[test]
+    fn unknown_key_id_does_not_verify() {
+        let mut keyring = keyring();
+        let tag = keyring.sign(MESSAGE).unwrap().replacen("v1", "v9", 1);
+        assert!(!keyring.verify(MESSAGE, &tag).unwrap());
+        assert!(keyring.needs_resign(&tag));
+        assert_eq!(
+            keyring.set_primary("v9"),
+            Err(KeyringError::UnknownKeyId("v9".to_string()))
+        );
+    }
+
+    #[test]
+    fn malformed_tags_are_errors() {
+        let keyring = keyring();
+        let tag = keyring.sign(MESSAGE).unwrap();
+        for malformed in [&tag[..tag.len() - 2], "v1:zz", "v1:", &format!("{}00", tag)] {
+            assert!(
+                matches!(
+                    keyring.verify(MESSAGE, malformed),
+                    Err(KeyringError::MalformedTag(_))
+                ),
+                "{}",
+                malformed
+            );
+        }
+    }
+}
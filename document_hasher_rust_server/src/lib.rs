@@ -1,35 +1,366 @@
-use pyo3::prelude::*;
-use sha2::{Digest, Sha256};
-use hmac::{Hmac, Mac};
-use hex; // Required for hex encoding HMAC output
+//! Document hashing and HMAC primitives for HashSure.
+//!
+//! The API here is plain Rust and needs no Python interpreter. The `document_hasher_rust`
+//! Python module is built on top of it when the `python` feature is enabled.
+
+pub mod algorithm;
+pub mod checksums;
+pub mod cose;
+mod digest;
+pub mod directory;
+pub mod ecdsa_p256;
+pub mod ed25519;
+mod encoding;
+pub mod error;
+pub mod jws;
+pub mod key;
+pub mod keyring;
+pub mod merkle;
+pub mod pdf;
+pub mod pdf_pages;
+pub mod pdf_revisions;
+pub mod pdf_signatures;
+pub mod pkcs7;
+#[cfg(feature = "python")]
+mod python;
+pub mod receipt;
+pub mod record;
+pub mod sha1cd;
+#[cfg(test)]
+mod test_support;
+pub mod timestamp;
+pub mod transparency;
+
+pub use algorithm::Algorithm;
+pub use digest::Digest;
+pub use error::HasherError;
+
+use rayon::prelude::*;
+use sha2::{Digest as _, Sha256};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// Default read size used by `sha256_file` when not memory-mapping the file.
+pub const DEFAULT_FILE_CHUNK_SIZE: usize = 1024 * 1024;
 
 /// Calculates the SHA256 hash of a given byte array.
-#[pyfunction]
-fn calculate_sha256_bytes(data: &[u8]) -> PyResult<String> {
-    let mut hasher = Sha256::new();
-    hasher.update(data);
-    let hash_result = hasher.finalize();
-    Ok(format!("{:x}", hash_result))
+pub fn sha256(data: &[u8]) -> Digest {
+    Algorithm::Sha256.digest(data)
 }
 
 /// Calculates the HMAC-SHA256 tag for a given message and secret key.
-/// The secret_key and message should be provided as bytes.
-/// Returns the HMAC tag as a hex-encoded string.
-#[pyfunction]
-fn calculate_hmac_sha256(secret_key_bytes: &[u8], message_bytes: &[u8]) -> PyResult<String> {
-    type HmacSha256 = Hmac<Sha256>;
-    let mut mac = HmacSha256::new_from_slice(secret_key_bytes)
-        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("HMAC key error: {}", e)))?;
-    mac.update(message_bytes);
-    let result = mac.finalize();
-    let code_bytes = result.into_bytes();
-    Ok(hex::encode(code_bytes))
-}
-
-/// A Python module implemented in Rust.
-#[pymodule]
-fn document_hasher_rust(m: &Bound<'_, PyModule>) -> PyResult<()> {
-    m.add_function(wrap_pyfunction!(calculate_sha256_bytes, m)?)?;
-    m.add_function(wrap_pyfunction!(calculate_hmac_sha256, m)?)?;
-    Ok(())
-}
\ No newline at end of file
+pub fn hmac_sha256(secret_key: &[u8], message: &[u8]) -> Result<Digest, HasherError> {
+    Algorithm::Sha256.hmac(secret_key, message)
+}
+
+/// Verifies an HMAC-SHA256 tag for a given message and secret key in constant time.
+pub fn verify_hmac_sha256(
+    secret_key: &[u8],
+    message: &[u8],
+    tag: &[u8],
+) -> Result<bool, HasherError> {
+    Algorithm::Sha256.verify_hmac(secret_key, message, tag)
+}
+
+/// An incremental SHA256 hasher, for feeding a document chunk by chunk instead of holding it all in memory.
+#[derive(Clone, Default)]
+pub struct Sha256Hasher {
+    hasher: Sha256,
+}
+
+impl Sha256Hasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds more bytes into the hasher.
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    /// Returns the digest of the data fed so far, leaving the hasher able to keep receiving data.
+    pub fn finalize(&self) -> Digest {
+        Digest::new(Algorithm::Sha256, self.hasher.clone().finalize().to_vec())
+    }
+}
+
+/// Calculates the SHA256 hash of the file at `path`.
+/// With `use_mmap` the file is memory-mapped; otherwise it is read in chunks of `chunk_size` bytes.
+pub fn sha256_file(
+    path: impl AsRef<Path>,
+    chunk_size: usize,
+    use_mmap: bool,
+) -> Result<Digest, HasherError> {
+    let path = path.as_ref();
+    if chunk_size == 0 {
+        return Err(HasherError::InvalidInput(
+            "chunk_size must be greater than zero".to_string(),
+        ));
+    }
+    read_sha256_file(path, chunk_size, use_mmap).map_err(|e| HasherError::io(path, e))
+}
+
+fn read_sha256_file(path: &Path, chunk_size: usize, use_mmap: bool) -> std::io::Result<Digest> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256Hasher::new();
+    // Only non-empty regular files can be mapped; everything else takes the read path.
+    let metadata = file.metadata()?;
+    if use_mmap && metadata.is_file() && metadata.len() > 0 {
+        // SAFETY: the mapping is only read while hashing; as with any mmap, a concurrent
+        // truncation of the file by another process may fault, which we accept here.
+        let mapped = unsafe { memmap2::Mmap::map(&file)? };
+        hasher.update(&mapped[..]);
+    } else {
+        let mut buffer = vec![0u8; chunk_size];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+    }
+    Ok(hasher.finalize())
+}
+
+/// Calculates the digest of the file at `path` with any supported algorithm.
+/// SHA256 goes through `sha256_file` (memory-mapped); other algorithms stream the file.
+pub fn digest_file(algorithm: Algorithm, path: impl AsRef<Path>) -> Result<Digest, HasherError> {
+    let path = path.as_ref();
+    if algorithm == Algorithm::Sha256 {
+        return sha256_file(path, DEFAULT_FILE_CHUNK_SIZE, true);
+    }
+    File::open(path)
+        .and_then(|file| algorithm.digest_reader(BufReader::new(file)))
+        .map_err(|e| HasherError::io(path, e))
+}
+
+/// A single input to `sha256_many`: either in-memory bytes or a file path.
+#[derive(Debug, Clone, Copy)]
+pub enum BatchInput<'a> {
+    Bytes(&'a [u8]),
+    Path(&'a Path),
+}
+
+/// Calculates the SHA256 hashes of many buffers and/or files in parallel on a rayon thread pool.
+/// Results are returned in input order, each with its own error, so one failure doesn't abort the batch.
+/// `max_workers` caps the number of threads; by default the global pool (one thread per CPU) is used.
+pub fn sha256_many(
+    inputs: &[BatchInput<'_>],
+    max_workers: Option<usize>,
+) -> Result<Vec<Result<Digest, HasherError>>, HasherError> {
+    let hash_all = || {
+        inputs
+            .par_iter()
+            .map(|input| match input {
+                BatchInput::Bytes(data) => Ok(sha256(data)),
+                BatchInput::Path(path) => sha256_file(path, DEFAULT_FILE_CHUNK_SIZE, true),
+            })
+            .collect()
+    };
+    match max_workers {
+        Some(0) => Err(HasherError::InvalidInput(
+            "max_workers must be greater than zero".to_string(),
+        )),
+        Some(threads) => {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .map_err(|e| HasherError::InvalidInput(format!("Thread pool error: {}", e)))?;
+            Ok(pool.install(hash_all))
+        }
+        None => Ok(hash_all()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    /// RFC 4231, test case 2.
+    const HMAC_KEY: &[u8] = b"Jefe";
+    const HMAC_MESSAGE: &[u8] = b"what do ya want for nothing?";
+    const HMAC_TAG: &str = "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+
+    #[test]
+    fn sha256_known_answers() {
+        assert_eq!(
+            sha256(b"").to_hex(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256(b"abc").to_hex(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(sha256(b"abc").algorithm(), Algorithm::Sha256);
+    }
+
+    #[test]
+    fn hmac_sha256_known_answers() {
+        assert_eq!(
+            hmac_sha256(HMAC_KEY, HMAC_MESSAGE).unwrap().to_hex(),
+            HMAC_TAG
+        );
+        // RFC 4231, test case 1.
+        let tag = hmac_sha256(&[0x0b; 20], b"Hi There").unwrap();
+        assert_eq!(
+            tag.to_hex(),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+    }
+
+    #[test]
+    fn verify_hmac_sha256_accepts_only_the_right_tag() {
+        let tag = hex::decode(HMAC_TAG).unwrap();
+        assert!(verify_hmac_sha256(HMAC_KEY, HMAC_MESSAGE, &tag).unwrap());
+        assert!(!verify_hmac_sha256(HMAC_KEY, b"what do ya want for something?", &tag).unwrap());
+        assert!(!verify_hmac_sha256(b"Jeff", HMAC_MESSAGE, &tag).unwrap());
+        let mut altered = tag.clone();
+        altered[31] ^= 1;
+        assert!(!verify_hmac_sha256(HMAC_KEY, HMAC_MESSAGE, &altered).unwrap());
+        for truncated in [&tag[..16], &[]] {
+            assert!(matches!(
+                verify_hmac_sha256(HMAC_KEY, HMAC_MESSAGE, truncated),
+                Err(HasherError::InvalidDigest(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn sha256_hasher_matches_one_shot() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let mut hasher = Sha256Hasher::new();
+        assert_eq!(hasher.finalize(), sha256(b""));
+        for chunk in data.chunks(777) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finalize(), sha256(&data));
+        // finalize leaves the hasher usable, and a clone continues independently.
+        let mut copy = hasher.clone();
+        hasher.update(b"more");
+        assert_eq!(hasher.finalize(), sha256(&[&data[..], b"more"].concat()));
+        assert_eq!(copy.finalize(), sha256(&data));
+        copy.update(b"other");
+        assert_eq!(copy.finalize(), sha256(&[&data[..], b"other"].concat()));
+    }
+
+    #[test]
+    fn sha256_file_matches_in_memory_hash_with_and_without_mmap() {
+        let dir = TempDir::new("lib-sha256-file");
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        dir.write("document", &data);
+        let path = dir.path().join("document");
+        let expected = sha256(&data);
+        assert_eq!(
+            sha256_file(&path, DEFAULT_FILE_CHUNK_SIZE, true).unwrap(),
+            expected
+        );
+        for chunk_size in [1, 4096, 99_999, 100_000, DEFAULT_FILE_CHUNK_SIZE] {
+            assert_eq!(sha256_file(&path, chunk_size, false).unwrap(), expected);
+        }
+        assert_eq!(digest_file(Algorithm::Sha256, &path).unwrap(), expected);
+        assert_eq!(
+            digest_file(Algorithm::Sha512, &path).unwrap(),
+            Algorithm::Sha512.digest(&data)
+        );
+    }
+
+    #[test]
+    fn empty_file_hashes_like_empty_input() {
+        let dir = TempDir::new("lib-empty-file");
+        dir.write("empty", b"");
+        let path = dir.path().join("empty");
+        for use_mmap in [true, false] {
+            assert_eq!(sha256_file(&path, 16, use_mmap).unwrap(), sha256(b""));
+        }
+    }
+
+    #[test]
+    fn sha256_file_rejects_a_zero_chunk_size() {
+        let dir = TempDir::new("lib-zero-chunk");
+        dir.write("document", b"abc");
+        assert!(matches!(
+            sha256_file(dir.path().join("document"), 0, false),
+            Err(HasherError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn missing_file_is_a_not_found_io_error() {
+        let dir = TempDir::new("lib-missing");
+        let path = dir.path().join("missing");
+        for use_mmap in [true, false] {
+            match sha256_file(&path, DEFAULT_FILE_CHUNK_SIZE, use_mmap) {
+                Err(HasherError::Io {
+                    path: Some(error_path),
+                    source,
+                }) => {
+                    assert_eq!(error_path, path);
+                    assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+                }
+                other => panic!("expected a NotFound error, got {:?}", other),
+            }
+        }
+    }
+
+    fn batch_digests(results: Vec<Result<Digest, HasherError>>) -> Vec<Digest> {
+        results.into_iter().map(|r| r.unwrap()).collect()
+    }
+
+    #[test]
+    fn sha256_many_returns_results_in_input_order() {
+        let dir = TempDir::new("lib-many-order");
+        dir.write("file", b"from a file");
+        let path = dir.path().join("file");
+        let blobs: Vec<Vec<u8>> = (0..64u8).map(|i| vec![i; i as usize * 97]).collect();
+        let mut inputs: Vec<BatchInput<'_>> =
+            blobs.iter().map(|blob| BatchInput::Bytes(blob)).collect();
+        inputs.insert(10, BatchInput::Path(&path));
+
+        let mut expected: Vec<Digest> = blobs.iter().map(|blob| sha256(blob)).collect();
+        expected.insert(10, sha256(b"from a file"));
+        assert_eq!(batch_digests(sha256_many(&inputs, None).unwrap()), expected);
+    }
+
+    #[test]
+    fn sha256_many_reports_unreadable_items_without_failing_the_batch() {
+        let dir = TempDir::new("lib-many-missing");
+        let missing = dir.path().join("missing");
+        let inputs = [
+            BatchInput::Bytes(b"abc"),
+            BatchInput::Path(&missing),
+            BatchInput::Bytes(b""),
+        ];
+        let results = sha256_many(&inputs, Some(2)).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), &sha256(b"abc"));
+        assert!(matches!(
+            &results[1],
+            Err(HasherError::Io { source, .. }) if source.kind() == std::io::ErrorKind::NotFound
+        ));
+        assert_eq!(results[2].as_ref().unwrap(), &sha256(b""));
+    }
+
+    #[test]
+    fn sha256_many_rejects_zero_workers() {
+        assert!(matches!(
+            sha256_many(&[BatchInput::Bytes(b"abc")], Some(0)),
+            Err(HasherError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn sha256_many_results_do_not_depend_on_the_worker_count() {
+        let blobs: Vec<Vec<u8>> = (0..32u8).map(|i| vec![i; 1000 + i as usize]).collect();
+        let inputs: Vec<BatchInput<'_>> =
+            blobs.iter().map(|blob| BatchInput::Bytes(blob)).collect();
+        let single = batch_digests(sha256_many(&inputs, Some(1)).unwrap());
+        assert_eq!(
+            batch_digests(sha256_many(&inputs, Some(4)).unwrap()),
+            single
+        );
+        assert_eq!(batch_digests(sha256_many(&inputs, None).unwrap()), single);
+    }
+}
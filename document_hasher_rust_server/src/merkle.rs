@@ -0,0 +1,466 @@
+//! Merkle tree over fixed-size chunks of a document, using the RFC 6962 tree shape and
+//! domain-separated hashing (`0x00` prefix for leaves, `0x01` for interior nodes), so a
+//! single chunk can be proven part of a document given only the root. The same tree functions back
+//! the registration log in `transparency`.
+
+use crate::error::HasherError;
+use sha2::{Digest, Sha256};
+
+/// A SHA256 node hash in the tree.
+pub type Hash = [u8; 32];
+
+/// Default chunk size for `MerkleTree`.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Hashes a single leaf (one chunk of the document).
+pub fn leaf_hash(chunk: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(chunk);
+    hasher.finalize().into()
+}
+
+/// Hashes an interior node from its two children.
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Largest power of two strictly smaller than `n` (`n` must be at least 2).
+fn split_point(n: usize) -> usize {
+    1 << (usize::BITS - 1 - (n - 1).leading_zeros())
+}
+
+/// Computes the root over a list of leaf hashes. The root of an empty tree is the hash of the empty string.
+pub fn root(leaves: &[Hash]) -> Hash {
+    match leaves.len() {
+        0 => Sha256::digest([]).into(),
+        1 => leaves[0],
+        n => {
+            let k = split_point(n);
+            node_hash(&root(&leaves[..k]), &root(&leaves[k..]))
+        }
+    }
+}
+
+/// Returns the audit path proving that the leaf at `index` is included in the tree, ordered from the leaf up.
+/// Returns `None` if `index` is out of range.
+pub fn inclusion_proof(leaves: &[Hash], index: usize) -> Option<Vec<Hash>> {
+    if index >= leaves.len() {
+        return None;
+    }
+    let mut proof = Vec::new();
+    collect_path(leaves, index, &mut proof);
+    Some(proof)
+}
+
+fn collect_path(leaves: &[Hash], index: usize, proof: &mut Vec<Hash>) {
+    let n = leaves.len();
+    if n <= 1 {
+        return;
+    }
+    let k = split_point(n);
+    if index < k {
+        collect_path(&leaves[..k], index, proof);
+        proof.push(root(&leaves[k..]));
+    } else {
+        collect_path(&leaves[k..], index - k, proof);
+        proof.push(root(&leaves[..k]));
+    }
+}
+
+/// Verifies an audit path for the leaf at `index` in a tree of `tree_size` leaves against `expected_root`
+/// (RFC 9162, section 2.1.3.2).
+pub fn verify_inclusion(
+    leaf: &Hash,
+    index: usize,
+    tree_size: usize,
+    proof: &[Hash],
+    expected_root: &Hash,
+) -> bool {
+    if index >= tree_size {
+        return false;
+    }
+    let (mut fnode, mut snode) = (index, tree_size - 1);
+    let mut computed = *leaf;
+    for sibling in proof {
+        if snode == 0 {
+            return false;
+        }
+        if fnode & 1 == 1 || fnode == snode {
+            computed = node_hash(sibling, &computed);
+            while fnode & 1 == 0 && fnode != 0 {
+                fnode >>= 1;
+                snode >>= 1;
+            }
+        } else {
+            computed = node_hash(&computed, sibling);
+        }
+        fnode >>= 1;
+        snode >>= 1;
+    }
+    snode == 0 && computed == *expected_root
+}
+
+/// Returns the consistency proof that the tree over the first `old_size` leaves is a prefix of the tree over
+/// all of `leaves` (RFC 6962, section 2.1.2). Returns `None` if `old_size` is zero or larger than the tree.
+pub fn consistency_proof(leaves: &[Hash], old_size: usize) -> Option<Vec<Hash>> {
+    if old_size == 0 || old_size > leaves.len() {
+        return None;
+    }
+    let mut proof = Vec::new();
+    collect_subproof(leaves, old_size, true, &mut proof);
+    Some(proof)
+}
+
+fn collect_subproof(leaves: &[Hash], m: usize, complete: bool, proof: &mut Vec<Hash>) {
+    let n = leaves.len();
+    if m == n {
+        if !complete {
+            proof.push(root(leaves));
+        }
+        return;
+    }
+    let k = split_point(n);
+    if m <= k {
+        collect_subproof(&leaves[..k], m, complete, proof);
+        proof.push(root(&leaves[k..]));
+    } else {
+        collect_subproof(&leaves[k..], m - k, false, proof);
+        proof.push(root(&leaves[..k]));
+    }
+}
+
+/// Verifies that the tree of `new_size` leaves with root `new_root` extends the tree of `old_size` leaves
+/// with root `old_root` (RFC 9162, section 2.1.4.2).
+pub fn verify_consistency(
+    old_size: usize,
+    new_size: usize,
+    proof: &[Hash],
+    old_root: &Hash,
+    new_root: &Hash,
+) -> bool {
+    if old_size == 0 || old_size > new_size {
+        return false;
+    }
+    if old_size == new_size {
+        return proof.is_empty() && old_root == new_root;
+    }
+    let mut path = proof.to_vec();
+    if old_size.is_power_of_two() {
+        path.insert(0, *old_root);
+    }
+    let Some((first, rest)) = path.split_first() else {
+        return false;
+    };
+    let (mut fnode, mut snode) = (old_size - 1, new_size - 1);
+    while fnode & 1 == 1 {
+        fnode >>= 1;
+        snode >>= 1;
+    }
+    let (mut old_computed, mut new_computed) = (*first, *first);
+    for node in rest {
+        if snode == 0 {
+            return false;
+        }
+        if fnode & 1 == 1 || fnode == snode {
+            old_computed = node_hash(node, &old_computed);
+            new_computed = node_hash(node, &new_computed);
+            while fnode & 1 == 0 && fnode != 0 {
+                fnode >>= 1;
+                snode >>= 1;
+            }
+        } else {
+            new_computed = node_hash(&new_computed, node);
+        }
+        fnode >>= 1;
+        snode >>= 1;
+    }
+    snode == 0 && old_computed == *old_root && new_computed == *new_root
+}
+
+/// A Merkle tree over a document split into `chunk_size`-byte chunks (the last one may be shorter).
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    chunk_size: usize,
+    leaves: Vec<Hash>,
+}
+
+impl MerkleTree {
+    /// Splits `data` into chunks and hashes each one. `chunk_size` must be non-zero.
+    pub fn from_bytes(data: &[u8], chunk_size: usize) -> Result<Self, HasherError> {
+        if chunk_size == 0 {
+            return Err(HasherError::InvalidInput(
+                "chunk_size must be greater than zero".to_string(),
+            ));
+        }
+        let leaves = data.chunks(chunk_size).map(leaf_hash).collect();
+        Ok(MerkleTree { chunk_size, leaves })
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    pub fn leaves(&self) -> &[Hash] {
+        &self.leaves
+    }
+
+    pub fn root(&self) -> Hash {
+        root(&self.leaves)
+    }
+
+    pub fn inclusion_proof(&self, index: usize) -> Option<Vec<Hash>> {
+        inclusion_proof(&self.leaves, index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The eight leaf inputs of the reference tree used by the RFC 6962 / RFC 9162 test vectors.
+    const LEAVES: [&[u8]; 8] = [
+        b"",
+        b"\x00",
+        b"\x10",
+        b"\x20\x21",
+        b"\x30\x31",
+        b"\x40\x41\x42\x43",
+        b"\x50\x51\x52\x53\x54\x55\x56\x57",
+        b"\x60\x61\x62\x63\x64\x65\x66\x67\x68\x69\x6a\x6b\x6c\x6d\x6e\x6f",
+    ];
+
+    /// The roots of the trees over the first 1 to 8 leaves.
+    const ROOTS: [&str; 8] = [
+        "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+        "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+        "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+        "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+        "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+        "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+        "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+    ];
+
+    fn leaves() -> Vec<Hash> {
+        LEAVES.iter().map(|leaf| leaf_hash(leaf)).collect()
+    }
+
+    fn hash(hex: &str) -> Hash {
+        hex::decode(hex).unwrap().try_into().unwrap()
+    }
+
+    fn hashes(hexes: &[&str]) -> Vec<Hash> {
+        hexes.iter().map(|hex| hash(hex)).collect()
+    }
+
+    #[test]
+    fn roots_match_reference_tree() {
+        let leaves = leaves();
+        assert_eq!(
+            hex::encode(root(&[])),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        for (size, expected) in (1..=8).zip(ROOTS) {
+            assert_eq!(
+                hex::encode(root(&leaves[..size])),
+                expected,
+                "tree of {} leaves",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn inclusion_proofs_match_reference_vectors() {
+        let leaves = leaves();
+        let vectors: [(usize, usize, &[&str]); 4] = [
+            (
+                0,
+                8,
+                &[
+                    "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+                ],
+            ),
+            (
+                5,
+                8,
+                &[
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                    "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                    "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+                ],
+            ),
+            (
+                2,
+                3,
+                &["fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"],
+            ),
+            (
+                1,
+                5,
+                &[
+                    "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                ],
+            ),
+        ];
+        for (index, size, expected) in vectors {
+            let proof = inclusion_proof(&leaves[..size], index).unwrap();
+            assert_eq!(proof, hashes(expected), "leaf {} of {}", index, size);
+            assert!(verify_inclusion(
+                &leaves[index],
+                index,
+                size,
+                &proof,
+                &hash(ROOTS[size - 1])
+            ));
+        }
+        assert_eq!(inclusion_proof(&leaves, 8), None);
+    }
+
+    #[test]
+    fn every_inclusion_proof_verifies_and_altered_ones_fail() {
+        let leaves = leaves();
+        for size in 1..=8 {
+            let tree_root = root(&leaves[..size]);
+            for index in 0..size {
+                let proof = inclusion_proof(&leaves[..size], index).unwrap();
+                assert!(verify_inclusion(
+                    &leaves[index],
+                    index,
+                    size,
+                    &proof,
+                    &tree_root
+                ));
+                let other = (index + 1) % size;
+                if other != index {
+                    assert!(!verify_inclusion(
+                        &leaves[other],
+                        index,
+                        size,
+                        &proof,
+                        &tree_root
+                    ));
+                    assert!(!verify_inclusion(
+                        &leaves[index],
+                        other,
+                        size,
+                        &proof,
+                        &tree_root
+                    ));
+                }
+                if let Some((_, shorter)) = proof.split_last() {
+                    assert!(!verify_inclusion(
+                        &leaves[index],
+                        index,
+                        size,
+                        shorter,
+                        &tree_root
+                    ));
+                }
+            }
+            assert!(!verify_inclusion(&leaves[0], size, size, &[], &tree_root));
+        }
+    }
+
+    #[test]
+    fn consistency_proofs_match_reference_vectors() {
+        let leaves = leaves();
+        let vectors: [(usize, usize, &[&str]); 4] = [
+            (1, 1, &[]),
+            (
+                1,
+                8,
+                &[
+                    "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+                ],
+            ),
+            (
+                6,
+                8,
+                &[
+                    "0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+                    "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                    "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+                ],
+            ),
+            (
+                2,
+                5,
+                &[
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                ],
+            ),
+        ];
+        for (old_size, new_size, expected) in vectors {
+            let proof = consistency_proof(&leaves[..new_size], old_size).unwrap();
+            assert_eq!(proof, hashes(expected), "{} to {}", old_size, new_size);
+            let (old_root, new_root) = (hash(ROOTS[old_size - 1]), hash(ROOTS[new_size - 1]));
+            assert!(verify_consistency(
+                old_size, new_size, &proof, &old_root, &new_root
+            ));
+        }
+        assert_eq!(consistency_proof(&leaves, 0), None);
+        assert_eq!(consistency_proof(&leaves[..4], 5), None);
+    }
+
+    #[test]
+    fn every_consistency_proof_verifies_and_altered_ones_fail() {
+        let leaves = leaves();
+        for new_size in 1..=8 {
+            let new_root = root(&leaves[..new_size]);
+            for old_size in 1..=new_size {
+                let old_root = root(&leaves[..old_size]);
+                let proof = consistency_proof(&leaves[..new_size], old_size).unwrap();
+                assert!(verify_consistency(
+                    old_size, new_size, &proof, &old_root, &new_root
+                ));
+                if old_size < new_size {
+                    assert!(!verify_consistency(
+                        old_size, new_size, &proof, &new_root, &old_root
+                    ));
+                }
+                if let Some((_, shorter)) = proof.split_last() {
+                    assert!(!verify_consistency(
+                        old_size, new_size, shorter, &old_root, &new_root
+                    ));
+                }
+            }
+            assert!(!verify_consistency(0, new_size, &[], &new_root, &new_root));
+        }
+    }
+
+    #[test]
+    fn tree_chunks_the_document() {
+        let tree = MerkleTree::from_bytes(b"abcdefgh", 3).unwrap();
+        assert_eq!(
+            tree.leaves(),
+            [leaf_hash(b"abc"), leaf_hash(b"def"), leaf_hash(b"gh")]
+        );
+        assert_eq!(
+            tree.root(),
+            node_hash(
+                &node_hash(&leaf_hash(b"abc"), &leaf_hash(b"def")),
+                &leaf_hash(b"gh")
+            )
+        );
+        assert!(matches!(
+            MerkleTree::from_bytes(b"abc", 0),
+            Err(HasherError::InvalidInput(_))
+        ));
+    }
+}
@@ -0,0 +1,369 @@
+//! A metadata-insensitive "canonical" hash of a PDF, reported next to the raw SHA256, so re-saving a document
+//! or touching only its metadata can be told apart from a change to its content.
+//!
+//! The PDF is parsed into its object graph and walked breadth-first from the document catalog (the trailer's
+//! `/Root`), so the trailer's `/ID` and `/Info` dictionary (`/Producer`, `/ModDate`, ...) are never reached.
+//! In every dictionary the volatile keys in `VOLATILE_KEYS` are skipped, which drops XMP metadata streams.
+//! Each object reached is serialized in a fixed binary form (see `CanonicalWriter`) in which:
+//!
+//! - references are replaced by the order in which the walk first reached the object, so object numbers,
+//!   generations, the cross-reference table and object streams make no difference;
+//! - dictionary entries are sorted by key;
+//! - streams are decoded where possible and hashed without `/Length`, `/Filter` and `/DecodeParms`, so
+//!   recompressing a stream makes no difference; streams that cannot be decoded (e.g. JPEG images) are hashed
+//!   as stored, with their filters;
+//! - literal and hex strings are the same.
+//!
+//! Anything not reachable from the catalog, including unused objects left over from earlier revisions,
+//! is ignored.
+
+use crate::encoding::push_field;
+use crate::{Algorithm, Digest, HasherError};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use sha2::{Digest as _, Sha256};
+use std::collections::{HashMap, VecDeque};
+
+/// Dictionary keys that change on re-save or only carry metadata: XMP streams, and application data
+/// stamped with the time of the last edit.
+pub const VOLATILE_KEYS: [&[u8]; 3] = [b"Metadata", b"PieceInfo", b"LastModified"];
+
+/// Streams decoding to more than this are hashed as stored rather than decoded.
+pub(crate) const MAX_DECODED_STREAM_SIZE: usize = 256 * 1024 * 1024;
+
+/// The raw and canonical SHA256 of a PDF.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PdfHashes {
+    /// SHA256 of the file's bytes, as calculated by `sha256`.
+    pub sha256: Digest,
+    /// SHA256 of the canonical object graph. Equal for two files that differ only in metadata or layout.
+    pub canonical_sha256: Digest,
+    /// Number of objects reached from the catalog.
+    pub object_count: usize,
+}
+
+impl PdfHashes {
+    /// Whether `other` has the same content, even if the files differ byte for byte.
+    pub fn same_content(&self, other: &PdfHashes) -> bool {
+        self.canonical_sha256 == other.canonical_sha256
+    }
+}
+
+fn malformed(reason: impl std::fmt::Display) -> HasherError {
+    HasherError::InvalidDigest(format!("Malformed PDF: {}", reason))
+}
+
+/// Parses a PDF held in memory. Encrypted documents are opened with the empty user password.
+pub(crate) fn load(data: &[u8]) -> Result<Document, HasherError> {
+    Document::load_mem(data).map_err(malformed)
+}
+
+/// Calculates the raw and canonical SHA256 of the PDF in `data`.
+pub fn hash_pdf(data: &[u8]) -> Result<PdfHashes, HasherError> {
+    let document = load(data)?;
+    let root = document
+        .trailer
+        .get(b"Root")
+        .and_then(Object::as_reference)
+        .map_err(|_| malformed("missing /Root"))?;
+    let mut writer = CanonicalWriter::new(&document);
+    let mut hasher = Sha256::new();
+    let object_count = writer.write_graph(root, |bytes| hasher.update(bytes));
+    Ok(PdfHashes {
+        sha256: Algorithm::Sha256.digest(data),
+        canonical_sha256: Digest::new(Algorithm::Sha256, hasher.finalize().to_vec()),
+        object_count,
+    })
+}
+
+/// Serializes objects of a document in the canonical form described in the module documentation.
+///
+/// Each value is a one-byte tag followed by its data: `n` null, `b` boolean (one byte), `i` integer
+/// (8 big-endian bytes), `r` real (the `f32` bits, 4 big-endian bytes), `N` name and `s` string (length-prefixed
+/// bytes), `a` array and `d` dictionary (a 4-byte count, then the items, or the length-prefixed keys each
+/// followed by its value), `S` stream (its dictionary, then the length-prefixed data) and `R` reference
+/// (the 4-byte walk index of the target). Lengths and counts are 4 big-endian bytes.
+pub(crate) struct CanonicalWriter<'a> {
+    document: &'a Document,
+    indices: HashMap<ObjectId, u32>,
+    queue: VecDeque<ObjectId>,
+}
+
+impl<'a> CanonicalWriter<'a> {
+    pub(crate) fn new(document: &'a Document) -> Self {
+        CanonicalWriter {
+            document,
+            indices: HashMap::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Walks the graph reachable from `start`, passing each object's serialization to `sink` in walk order.
+    /// Objects already reached by an earlier call are not written again. Returns the number written.
+    pub(crate) fn write_graph(&mut self, start: ObjectId, sink: impl FnMut(&[u8])) -> usize {
+        self.index_of(start);
+        self.write_queued(sink)
+    }
+
+    /// Writes `root`, a direct object, then the graph reachable from it, as `write_graph` does.
+    pub(crate) fn write_tree(&mut self, root: &Object, mut sink: impl FnMut(&[u8])) -> usize {
+        let mut out = Vec::new();
+        self.write_object(root, &mut out);
+        sink(&out);
+        1 + self.write_queued(sink)
+    }
+
+    fn write_queued(&mut self, mut sink: impl FnMut(&[u8])) -> usize {
+        let mut out = Vec::new();
+        let mut written = 0;
+        while let Some(id) = self.queue.pop_front() {
+            out.clear();
+            match self.document.get_object(id) {
+                Ok(object) => self.write_object(object, &mut out),
+                // A reference to a missing object is a reference to null.
+                Err(_) => out.push(b'n'),
+            }
+            sink(&out);
+            written += 1;
+        }
+        written
+    }
+
+    fn index_of(&mut self, id: ObjectId) -> u32 {
+        let next = self.indices.len() as u32;
+        *self.indices.entry(id).or_insert_with(|| {
+            self.queue.push_back(id);
+            next
+        })
+    }
+
+    fn write_object(&mut self, object: &Object, out: &mut Vec<u8>) {
+        match object {
+            Object::Null => out.push(b'n'),
+            Object::Boolean(value) => out.extend_from_slice(&[b'b', *value as u8]),
+            Object::Integer(value) => {
+                out.push(b'i');
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Object::Real(value) => {
+                out.push(b'r');
+                // Adding zero folds -0.0 into 0.0.
+                out.extend_from_slice(&(value + 0.0).to_bits().to_be_bytes());
+            }
+            Object::Name(name) => {
+                out.push(b'N');
+                push_field(out, name);
+            }
+            Object::String(bytes, _) => {
+                out.push(b's');
+                push_field(out, bytes);
+            }
+            Object::Array(items) => {
+                out.push(b'a');
+                out.extend_from_slice(&(items.len() as u32).to_be_bytes());
+                for item in items {
+                    self.write_object(item, out);
+                }
+            }
+            Object::Dictionary(dictionary) => self.write_dictionary(dictionary, &[], out),
+            Object::Stream(stream) => {
+                out.push(b'S');
+                match stream.decompressed_content_with_limit(MAX_DECODED_STREAM_SIZE) {
+                    Ok(content) => {
+                        self.write_dictionary(
+                            &stream.dict,
+                            &[b"Length", b"Filter", b"DecodeParms", b"DL"],
+                            out,
+                        );
+                        push_field(out, &content);
+                    }
+                    Err(_) => {
+                        self.write_dictionary(&stream.dict, &[b"Length"], out);
+                        push_field(out, &stream.content);
+                    }
+                }
+            }
+            Object::Reference(id) => {
+                let index = self.index_of(*id);
+                out.push(b'R');
+                out.extend_from_slice(&index.to_be_bytes());
+            }
+        }
+    }
+
+    fn write_dictionary(&mut self, dictionary: &Dictionary, skip: &[&[u8]], out: &mut Vec<u8>) {
+        let mut entries: Vec<(&Vec<u8>, &Object)> = dictionary
+            .iter()
+            .filter(|(key, _)| {
+                !VOLATILE_KEYS.contains(&key.as_slice()) && !skip.contains(&key.as_slice())
+            })
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        out.push(b'd');
+        out.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        for (key, value) in entries {
+            push_field(out, key);
+            self.write_object(value, out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{Stream, StringFormat, dictionary};
+
+    /// How to write a one-page test PDF; each field varies one thing a re-save or metadata edit could change.
+    #[derive(Clone)]
+    struct Fixture {
+        content: &'static [u8],
+        producer: &'static str,
+        mod_date: &'static str,
+        id: &'static [u8],
+        /// XMP packet attached to the catalog as `/Metadata`, if any.
+        xmp: Option<&'static [u8]>,
+        compress: bool,
+        /// Object numbers start after this.
+        first_object: u32,
+    }
+
+    /// Long and repetitive enough for `Stream::compress`, which leaves streams that don't shrink uncompressed.
+    const CONTENT: &[u8] = b"BT /F1 12 Tf 72 720 Td (hello) Tj ET\n\
+        BT /F1 12 Tf 72 700 Td (hello) Tj ET\n\
+        BT /F1 12 Tf 72 680 Td (hello) Tj ET\n";
+    const EDITED_CONTENT: &[u8] = b"BT /F1 12 Tf 72 720 Td (hello) Tj ET\n\
+        BT /F1 12 Tf 72 700 Td (hellO) Tj ET\n\
+        BT /F1 12 Tf 72 680 Td (hello) Tj ET\n";
+
+    impl Default for Fixture {
+        fn default() -> Self {
+            Fixture {
+                content: CONTENT,
+                producer: "HashSure tests",
+                mod_date: "D:20240101000000Z",
+                id: b"0123456789abcdef",
+                xmp: None,
+                compress: false,
+                first_object: 0,
+            }
+        }
+    }
+
+    impl Fixture {
+        fn build(&self) -> Vec<u8> {
+            let mut document = Document::with_version("1.7");
+            document.max_id = self.first_object;
+            let pages_id = document.new_object_id();
+            let font_id = document.add_object(dictionary! {
+                "Type" => "Font",
+                "Subtype" => "Type1",
+                "BaseFont" => "Helvetica",
+            });
+            let mut content = Stream::new(dictionary! {}, self.content.to_vec());
+            if self.compress {
+                content.compress().unwrap();
+            }
+            assert_eq!(content.dict.has(b"Filter"), self.compress);
+            let content_id = document.add_object(content);
+            let page_id = document.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+                "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+                "Contents" => content_id,
+            });
+            document.objects.insert(
+                pages_id,
+                Object::Dictionary(dictionary! {
+                    "Type" => "Pages",
+                    "Kids" => vec![page_id.into()],
+                    "Count" => 1,
+                }),
+            );
+            let mut catalog = dictionary! { "Type" => "Catalog", "Pages" => pages_id };
+            if let Some(xmp) = self.xmp {
+                let metadata = Stream::new(
+                    dictionary! { "Type" => "Metadata", "Subtype" => "XML" },
+                    xmp.to_vec(),
+                );
+                catalog.set("Metadata", document.add_object(metadata));
+            }
+            let catalog_id = document.add_object(catalog);
+            let info_id = document.add_object(dictionary! {
+                "Producer" => Object::string_literal(self.producer),
+                "ModDate" => Object::string_literal(self.mod_date),
+            });
+            let id = Object::String(self.id.to_vec(), StringFormat::Hexadecimal);
+            document.trailer.set("Root", catalog_id);
+            document.trailer.set("Info", info_id);
+            document.trailer.set("ID", vec![id.clone(), id]);
+            let mut data = Vec::new();
+            document.save_to(&mut data).unwrap();
+            data
+        }
+    }
+
+    fn hashes(fixture: Fixture) -> PdfHashes {
+        hash_pdf(&fixture.build()).unwrap()
+    }
+
+    #[test]
+    fn metadata_and_layout_changes_keep_the_canonical_hash() {
+        let original = hashes(Fixture::default());
+        assert_eq!(original.object_count, 5);
+        let resaved = [
+            Fixture {
+                mod_date: "D:20250601120000Z",
+                ..Default::default()
+            },
+            Fixture {
+                producer: "Another PDF writer 2.0",
+                ..Default::default()
+            },
+            Fixture {
+                id: b"fedcba9876543210",
+                ..Default::default()
+            },
+            Fixture {
+                xmp: Some(b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>"),
+                ..Default::default()
+            },
+            Fixture {
+                compress: true,
+                ..Default::default()
+            },
+            Fixture {
+                first_object: 40,
+                ..Default::default()
+            },
+        ];
+        for (i, fixture) in resaved.into_iter().enumerate() {
+            let hashes = hashes(fixture);
+            assert_ne!(hashes.sha256, original.sha256, "fixture {}", i);
+            assert!(hashes.same_content(&original), "fixture {}", i);
+        }
+    }
+
+    #[test]
+    fn content_changes_change_the_canonical_hash() {
+        let original = hashes(Fixture::default());
+        let edited = hashes(Fixture {
+            content: EDITED_CONTENT,
+            ..Default::default()
+        });
+        assert!(!edited.same_content(&original));
+        let edited_and_compressed = hashes(Fixture {
+            content: EDITED_CONTENT,
+            compress: true,
+            ..Default::default()
+        });
+        assert!(edited_and_compressed.same_content(&edited));
+    }
+
+    #[test]
+    fn unparseable_pdf_is_an_error() {
+        assert!(matches!(
+            hash_pdf(b"%PDF-1.7\nnot really"),
+            Err(HasherError::InvalidDigest(_))
+        ));
+    }
+}
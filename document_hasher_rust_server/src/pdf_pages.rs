@@ -0,0 +1,489 @@
+//! Per-page hashes of a PDF, so a failed verification can say which pages changed.
+//!
+//! A page's SHA256 covers the label `hashsure-pdf-page-v1` and a zero byte, then its content: the decoded
+//! content streams joined by newlines, preceded by their length as 4 big-endian bytes. It then covers the
+//! canonical form (see `pdf::CanonicalWriter`) of a dictionary holding the page's effective `/Resources`,
+//! `/MediaBox`, `/CropBox` and `/Rotate` (inherited from the page tree where the page does not set them),
+//! followed by everything those reference: fonts, images and form XObjects. Metadata, annotations and the
+//! page's position in the tree are not covered, so a page hashes the same wherever it appears.
+//!
+//! The document root is the `merkle` root over the page hashes in page order.
+
+use crate::encoding::push_field;
+use crate::merkle::{self, Hash};
+use crate::pdf::{self, CanonicalWriter, MAX_DECODED_STREAM_SIZE};
+use crate::{Algorithm, Digest, HasherError};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use sha2::{Digest as _, Sha256};
+use std::collections::HashSet;
+
+const PAGE_LABEL: &[u8] = b"hashsure-pdf-page-v1\0";
+
+/// Page attributes covered by a page's hash, all of which a page may inherit from its ancestors.
+const INHERITED_KEYS: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// Beyond this many levels the page tree is assumed to be cyclic.
+const MAX_TREE_DEPTH: usize = 64;
+
+/// The hash of every page of a PDF, in page order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageHashes {
+    pub pages: Vec<Digest>,
+    /// The Merkle root over the page hashes.
+    pub root: Hash,
+}
+
+/// Hashes every page of the PDF in `data`.
+pub fn hash_pages(data: &[u8]) -> Result<PageHashes, HasherError> {
+    let document = pdf::load(data)?;
+    let pages = document
+        .page_iter()
+        .map(|page_id| hash_page(&document, page_id))
+        .collect::<Result<Vec<_>, _>>()?;
+    let leaves: Vec<Hash> = pages
+        .iter()
+        .map(|page| merkle::leaf_hash(page.as_bytes()))
+        .collect();
+    Ok(PageHashes {
+        root: merkle::root(&leaves),
+        pages,
+    })
+}
+
+fn hash_page(document: &Document, page_id: ObjectId) -> Result<Digest, HasherError> {
+    let content = document
+        .get_page_content_with_limit(page_id, MAX_DECODED_STREAM_SIZE)
+        .map_err(|e| {
+            HasherError::InvalidDigest(format!("Malformed PDF: page {:?}: {}", page_id, e))
+        })?;
+    let mut hasher = Sha256::new();
+    let mut header = PAGE_LABEL.to_vec();
+    push_field(&mut header, &content);
+    hasher.update(&header);
+
+    let mut attributes = Dictionary::new();
+    for key in INHERITED_KEYS {
+        if let Some(value) = inherited(document, page_id, key) {
+            attributes.set(key, value.clone());
+        }
+    }
+    CanonicalWriter::new(document).write_tree(&Object::Dictionary(attributes), |bytes| {
+        hasher.update(bytes)
+    });
+    Ok(Digest::new(Algorithm::Sha256, hasher.finalize().to_vec()))
+}
+
+/// Looks `key` up on the page, then on each ancestor in the page tree.
+fn inherited<'a>(document: &'a Document, page_id: ObjectId, key: &[u8]) -> Option<&'a Object> {
+    let mut node = document.get_dictionary(page_id).ok()?;
+    for _ in 0..MAX_TREE_DEPTH {
+        if let Ok(value) = node.get(key) {
+            return Some(value);
+        }
+        let parent = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = document.get_dictionary(parent).ok()?;
+    }
+    None
+}
+
+/// The pages that differ between a registered and a submitted document. Page numbers are 1-based.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageComparison {
+    /// Pages of the submitted document with no counterpart in the registered one.
+    pub added: Vec<usize>,
+    /// Pages of the registered document with no counterpart in the submitted one.
+    pub removed: Vec<usize>,
+    /// `(registered, submitted)` page numbers of pages that were changed in place.
+    pub modified: Vec<(usize, usize)>,
+}
+
+impl PageComparison {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// The diff below gives up after this many steps (roughly, page comparisons) rather than run on: its cost grows
+/// with the number of pages times the number of differences.
+pub const MAX_DIFF_STEPS: usize = 1 << 24;
+
+/// Compares two page-hash lists. Pages are matched in order along a longest common subsequence of hashes;
+/// between two matched pages, unmatched pages are paired off as modified, and any left over are added or removed.
+/// Fails with `InvalidInput` if the lists differ too much to compare within `MAX_DIFF_STEPS`.
+pub fn compare_pages(
+    registered: &[Digest],
+    submitted: &[Digest],
+) -> Result<PageComparison, HasherError> {
+    let mut matches = Vec::new();
+    let submitted_pages: HashSet<&[u8]> = submitted.iter().map(Digest::as_bytes).collect();
+    // Two documents with no page in common need no diff, however long they are.
+    if registered
+        .iter()
+        .any(|page| submitted_pages.contains(page.as_bytes()))
+    {
+        let mut budget = MAX_DIFF_STEPS;
+        diff(registered, submitted, (0, 0), &mut budget, &mut matches).ok_or_else(|| {
+            HasherError::InvalidInput(format!(
+                "Too many changed pages to compare: {} registered against {} submitted",
+                registered.len(),
+                submitted.len()
+            ))
+        })?;
+    }
+
+    let mut comparison = PageComparison::default();
+    let (mut i, mut j) = (0, 0);
+    for (matched_i, matched_j) in matches
+        .into_iter()
+        .chain([(registered.len(), submitted.len())])
+    {
+        let paired = (matched_i - i).min(matched_j - j);
+        comparison.modified.extend(
+            (i..i + paired)
+                .zip(j..j + paired)
+                .map(|(i, j)| (i + 1, j + 1)),
+        );
+        comparison.removed.extend(i + paired + 1..=matched_i);
+        comparison.added.extend(j + paired + 1..=matched_j);
+        (i, j) = (matched_i + 1, matched_j + 1);
+    }
+    Ok(comparison)
+}
+
+/// Appends the index pairs, shifted by `offset`, of a longest common subsequence of `a` and `b` to `matches`,
+/// in order. This is Myers' linear-space diff: strip the common prefix and suffix, find the middle of an
+/// optimal edit path, and recurse on either side of it. Returns `None` once `budget` steps are used up.
+fn diff(
+    a: &[Digest],
+    b: &[Digest],
+    offset: (usize, usize),
+    budget: &mut usize,
+    matches: &mut Vec<(usize, usize)>,
+) -> Option<()> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    matches.extend((0..prefix).map(|k| (offset.0 + k, offset.1 + k)));
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+    let offset = (offset.0 + prefix, offset.1 + prefix);
+
+    if a.len() == 1 || b.len() == 1 {
+        let (single, other, single_is_a) = if a.len() == 1 {
+            (&a[0], b, true)
+        } else {
+            (&b[0], a, false)
+        };
+        if let Some(k) = other.iter().position(|page| page == single) {
+            matches.push(if single_is_a {
+                (offset.0, offset.1 + k)
+            } else {
+                (offset.0 + k, offset.1)
+            });
+        }
+    } else if !a.is_empty()
+        && !b.is_empty()
+        && let Some((x, y)) = middle_snake(a, b, budget)?
+    {
+        diff(&a[..x], &b[..y], offset, budget, matches)?;
+        diff(
+            &a[x..],
+            &b[y..],
+            (offset.0 + x, offset.1 + y),
+            budget,
+            matches,
+        )?;
+    }
+    let (end_a, end_b) = (offset.0 + a.len(), offset.1 + b.len());
+    matches.extend((0..suffix).map(|k| (end_a + k, end_b + k)));
+    Some(())
+}
+
+/// Finds a point `(x, y)` on an optimal edit path from `(0, 0)` to `(a.len(), b.len())` by running the greedy
+/// search forwards and backwards until they meet. Both inputs must be non-empty and differ in their first and
+/// last pages. Returns `Some(None)` if the only path shares nothing, and `None` if `budget` runs out.
+fn middle_snake(a: &[Digest], b: &[Digest], budget: &mut usize) -> Option<Option<(usize, usize)>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max_d = (n + m + 1) / 2;
+    let v_offset = max_d;
+    let v_length = (2 * max_d + 2) as usize;
+    *budget = budget.checked_sub(v_length)?;
+    // forward[k] and backward[k] are the furthest x reached on diagonal k - v_offset, or -1.
+    let mut forward = vec![-1isize; v_length];
+    let mut backward = vec![-1isize; v_length];
+    forward[(v_offset + 1) as usize] = 0;
+    backward[(v_offset + 1) as usize] = 0;
+    let delta = n - m;
+    let front = delta % 2 != 0;
+    let (mut k1_start, mut k1_end, mut k2_start, mut k2_end) = (0, 0, 0, 0);
+    for d in 0..max_d {
+        let mut k1 = -d + k1_start;
+        while k1 <= d - k1_end {
+            let k1_offset = (v_offset + k1) as usize;
+            let mut x1 = if k1 == -d || (k1 != d && forward[k1_offset - 1] < forward[k1_offset + 1])
+            {
+                forward[k1_offset + 1]
+            } else {
+                forward[k1_offset - 1] + 1
+            };
+            let (mut y1, start) = (x1 - k1, x1);
+            while x1 < n && y1 < m && a[x1 as usize] == b[y1 as usize] {
+                x1 += 1;
+                y1 += 1;
+            }
+            *budget = budget.checked_sub(1 + (x1 - start) as usize)?;
+            forward[k1_offset] = x1;
+            if x1 > n {
+                k1_end += 2;
+            } else if y1 > m {
+                k1_start += 2;
+            } else if front {
+                let k2_offset = v_offset + delta - k1;
+                if (0..v_length as isize).contains(&k2_offset) && backward[k2_offset as usize] != -1
+                {
+                    let x2 = n - backward[k2_offset as usize];
+                    if x1 >= x2 {
+                        return Some(split(x1, y1, n, m));
+                    }
+                }
+            }
+            k1 += 2;
+        }
+
+        let mut k2 = -d + k2_start;
+        while k2 <= d - k2_end {
+            let k2_offset = (v_offset + k2) as usize;
+            let mut x2 =
+                if k2 == -d || (k2 != d && backward[k2_offset - 1] < backward[k2_offset + 1]) {
+                    backward[k2_offset + 1]
+                } else {
+                    backward[k2_offset - 1] + 1
+                };
+            let (mut y2, start) = (x2 - k2, x2);
+            while x2 < n && y2 < m && a[(n - x2 - 1) as usize] == b[(m - y2 - 1) as usize] {
+                x2 += 1;
+                y2 += 1;
+            }
+            *budget = budget.checked_sub(1 + (x2 - start) as usize)?;
+            backward[k2_offset] = x2;
+            if x2 > n {
+                k2_end += 2;
+            } else if y2 > m {
+                k2_start += 2;
+            } else if !front {
+                let k1_offset = v_offset + delta - k2;
+                if (0..v_length as isize).contains(&k1_offset) && forward[k1_offset as usize] != -1
+                {
+                    let x1 = forward[k1_offset as usize];
+                    let y1 = v_offset + x1 - k1_offset;
+                    if x1 >= n - x2 {
+                        return Some(split(x1, y1, n, m));
+                    }
+                }
+            }
+            k2 += 2;
+        }
+    }
+    Some(None)
+}
+
+/// A split point, or `None` if it would not divide the problem.
+fn split(x: isize, y: isize, n: isize, m: isize) -> Option<(usize, usize)> {
+    ((x, y) != (0, 0) && (x, y) != (n, m)).then_some((x as usize, y as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One page per label; pages with equal labels have equal hashes.
+    fn pages(labels: impl IntoIterator<Item = u32>) -> Vec<Digest> {
+        labels
+            .into_iter()
+            .map(|label| Digest::new(Algorithm::Sha256, [label.to_be_bytes(); 8].concat()))
+            .collect()
+    }
+
+    fn compare(registered: &[u32], submitted: &[u32]) -> PageComparison {
+        compare_pages(
+            &pages(registered.iter().copied()),
+            &pages(submitted.iter().copied()),
+        )
+        .unwrap()
+    }
+
+    /// Length of a longest common subsequence, by the quadratic table.
+    fn lcs_length(a: &[u32], b: &[u32]) -> usize {
+        let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in 0..a.len() {
+            for j in 0..b.len() {
+                table[i + 1][j + 1] = if a[i] == b[j] {
+                    table[i][j] + 1
+                } else {
+                    table[i][j + 1].max(table[i + 1][j])
+                };
+            }
+        }
+        table[a.len()][b.len()]
+    }
+
+    #[test]
+    fn identical_documents_have_no_differences() {
+        assert!(compare(&[1, 2, 3], &[1, 2, 3]).is_empty());
+        assert!(compare(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn pages_are_added_removed_and_modified() {
+        assert_eq!(
+            compare(&[1, 2, 3], &[1, 2, 9, 3]),
+            PageComparison {
+                added: vec![3],
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            compare(&[1, 2, 3], &[1, 3]),
+            PageComparison {
+                removed: vec![2],
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            compare(&[1, 2, 3], &[1, 9, 3]),
+            PageComparison {
+                modified: vec![(2, 2)],
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            compare(&[1, 2, 3, 4], &[1, 8, 9, 4, 5]),
+            PageComparison {
+                added: vec![5],
+                removed: vec![],
+                modified: vec![(2, 2), (3, 3)]
+            }
+        );
+        assert_eq!(
+            compare(&[], &[1, 2]),
+            PageComparison {
+                added: vec![1, 2],
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            compare(&[1, 2], &[]),
+            PageComparison {
+                removed: vec![1, 2],
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn moved_page_is_removed_and_added() {
+        assert_eq!(
+            compare(&[1, 2, 3], &[3, 1, 2]),
+            PageComparison {
+                added: vec![1],
+                removed: vec![3],
+                modified: vec![]
+            }
+        );
+    }
+
+    #[test]
+    fn documents_without_common_pages_are_all_modified() {
+        assert_eq!(
+            compare(&[1, 2], &[7, 8, 9]),
+            PageComparison {
+                added: vec![3],
+                removed: vec![],
+                modified: vec![(1, 1), (2, 2)]
+            }
+        );
+    }
+
+    #[test]
+    fn unmatched_pages_lie_on_a_longest_common_subsequence() {
+        // A small linear congruential generator keeps the cases reproducible.
+        let mut state: u32 = 12345;
+        let mut next = |bound: u32| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) % bound
+        };
+        for _ in 0..2000 {
+            let registered: Vec<u32> = (0..next(12)).map(|_| next(4)).collect();
+            let submitted: Vec<u32> = (0..next(12)).map(|_| next(4)).collect();
+            let comparison = compare(&registered, &submitted);
+            let unmatched_registered: HashSet<usize> = comparison
+                .removed
+                .iter()
+                .copied()
+                .chain(comparison.modified.iter().map(|&(i, _)| i))
+                .collect();
+            let unmatched_submitted: HashSet<usize> = comparison
+                .added
+                .iter()
+                .copied()
+                .chain(comparison.modified.iter().map(|&(_, j)| j))
+                .collect();
+            let matched_registered: Vec<u32> = (1..=registered.len())
+                .filter(|i| !unmatched_registered.contains(i))
+                .map(|i| registered[i - 1])
+                .collect();
+            let matched_submitted: Vec<u32> = (1..=submitted.len())
+                .filter(|j| !unmatched_submitted.contains(j))
+                .map(|j| submitted[j - 1])
+                .collect();
+            assert_eq!(
+                matched_registered, matched_submitted,
+                "{:?} {:?}",
+                registered, submitted
+            );
+            assert_eq!(
+                matched_registered.len(),
+                lcs_length(&registered, &submitted),
+                "{:?} {:?}",
+                registered,
+                submitted
+            );
+        }
+    }
+
+    #[test]
+    fn long_documents_with_few_changes_are_compared() {
+        let registered: Vec<u32> = (0..100_000).collect();
+        let mut submitted = registered.clone();
+        submitted[10] = 1_000_000;
+        submitted.splice(50_000..50_000, 2_000_000..2_000_500);
+        submitted.remove(99_000);
+        let comparison = compare(&registered, &submitted);
+        assert_eq!(comparison.modified, vec![(11, 11)]);
+        assert_eq!(comparison.added, (50_001..=50_500).collect::<Vec<_>>());
+        assert_eq!(comparison.removed, vec![98_501]);
+    }
+
+    #[test]
+    fn diff_stops_when_its_budget_runs_out() {
+        // A reversed document matches one page at most, after a search over every diagonal.
+        let registered = pages(0..200);
+        let reversed = pages((0..200).rev());
+        let mut budget = 1000;
+        assert_eq!(
+            diff(&registered, &reversed, (0, 0), &mut budget, &mut Vec::new()),
+            None
+        );
+        let mut budget = MAX_DIFF_STEPS;
+        let mut matches = Vec::new();
+        assert_eq!(
+            diff(&registered, &reversed, (0, 0), &mut budget, &mut matches),
+            Some(())
+        );
+        assert_eq!(matches.len(), 1);
+    }
+}
@@ -0,0 +1,324 @@
+//! Incremental-update revisions of a PDF. Editors append changes after the original `%%EOF` rather than
+//! rewriting the file, so a modified document usually starts with the exact bytes of every earlier revision.
+//!
+//! A revision ends at a `%%EOF` marker directly preceded by `startxref` and an offset (only whitespace
+//! in between), plus one optional end-of-line. A `startxref 0` marker is skipped: linearized files end their
+//! first-page section with one. Each revision's SHA256 covers the file from its first byte to the end of that
+//! revision, so it equals the raw SHA256 the server would have registered for the file as it was then.
+
+use crate::{Algorithm, Digest};
+use sha2::{Digest as _, Sha256};
+
+const EOF_MARKER: &[u8] = b"%%EOF";
+const STARTXREF: &[u8] = b"startxref";
+
+/// One revision boundary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revision {
+    /// Offset just past the revision's `%%EOF` and end-of-line, i.e. the length of the file as of this revision.
+    pub end: usize,
+    /// Offset just past the revision's `%%EOF`.
+    pub marker_end: usize,
+    /// The cross-reference offset given by the revision's `startxref`.
+    pub startxref: u64,
+    /// SHA256 of the file up to `end`.
+    pub sha256: Digest,
+    /// SHA256 of the file up to the end of `%%EOF`, if an end-of-line follows it, for files saved without one.
+    pub sha256_before_eol: Option<Digest>,
+}
+
+/// The revisions of a PDF, in file order (the original first).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RevisionAnalysis {
+    pub revisions: Vec<Revision>,
+    /// Bytes after the last revision, which no PDF reader will interpret as part of the document.
+    pub trailing_bytes: usize,
+    /// SHA256 of the whole file.
+    pub sha256: Digest,
+}
+
+/// How a submitted file relates to the registered file it extends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RevisionMatch {
+    /// Length of the registered file, which is a prefix of the submitted one.
+    pub registered_length: usize,
+    /// The registered digest that matched.
+    pub sha256: Digest,
+    /// Number of complete revisions appended after the registered file.
+    pub appended_revisions: usize,
+    /// Bytes appended after the last complete revision.
+    pub trailing_bytes: usize,
+}
+
+impl RevisionMatch {
+    /// Whether the submitted file is the registered file, byte for byte.
+    pub fn is_exact(&self) -> bool {
+        self.appended_revisions == 0 && self.trailing_bytes == 0
+    }
+
+    /// A summary such as "original plus 2 appended revisions".
+    pub fn describe(&self) -> String {
+        let mut description = "original".to_string();
+        match self.appended_revisions {
+            0 => {}
+            1 => description.push_str(" plus 1 appended revision"),
+            n => description.push_str(&format!(" plus {} appended revisions", n)),
+        }
+        if self.trailing_bytes > 0 {
+            let joiner = if self.appended_revisions > 0 {
+                " and"
+            } else {
+                " plus"
+            };
+            description.push_str(&format!(
+                "{} {} trailing bytes",
+                joiner, self.trailing_bytes
+            ));
+        }
+        description
+    }
+}
+
+/// Finds every revision boundary of the PDF in `data` and hashes each revision prefix, in one pass.
+pub fn analyze(data: &[u8]) -> RevisionAnalysis {
+    let mut boundaries = Vec::new();
+    let mut search_from = 0;
+    while let Some(found) = find(&data[search_from..], EOF_MARKER) {
+        let marker = search_from + found;
+        search_from = marker + EOF_MARKER.len();
+        let Some(startxref) = startxref_before(&data[..marker]) else {
+            continue;
+        };
+        if startxref == 0 {
+            continue;
+        }
+        let eol = match &data[search_from..] {
+            [b'\r', b'\n', ..] => 2,
+            [b'\r' | b'\n', ..] => 1,
+            _ => 0,
+        };
+        boundaries.push((search_from, search_from + eol, startxref));
+    }
+
+    let mut hasher = Sha256::new();
+    let mut hashed = 0;
+    let prefix_digest = |hasher: &mut Sha256, hashed: &mut usize, end: usize| {
+        hasher.update(&data[*hashed..end]);
+        *hashed = end;
+        Digest::new(Algorithm::Sha256, hasher.clone().finalize().to_vec())
+    };
+    let revisions = boundaries
+        .into_iter()
+        .map(|(marker_end, end, startxref)| {
+            let before_eol = prefix_digest(&mut hasher, &mut hashed, marker_end);
+            let sha256 = prefix_digest(&mut hasher, &mut hashed, end);
+            Revision {
+                end,
+                marker_end,
+                startxref,
+                sha256,
+                sha256_before_eol: (end != marker_end).then_some(before_eol),
+            }
+        })
+        .collect::<Vec<_>>();
+    let sha256 = prefix_digest(&mut hasher, &mut hashed, data.len());
+    let trailing_bytes = data.len() - revisions.last().map_or(0, |revision| revision.end);
+    RevisionAnalysis {
+        revisions,
+        trailing_bytes,
+        sha256,
+    }
+}
+
+impl RevisionAnalysis {
+    /// Number of revisions appended after the original.
+    pub fn appended_revisions(&self) -> usize {
+        self.revisions.len().saturating_sub(1)
+    }
+
+    /// Finds the longest prefix of the file whose SHA256 is one of `registered`: the whole file, or the file as
+    /// of one of its revisions. Returns `None` if the file does not extend any registered file.
+    pub fn find_registered(&self, registered: &[Digest]) -> Option<RevisionMatch> {
+        let total_length =
+            self.revisions.last().map_or(0, |revision| revision.end) + self.trailing_bytes;
+        if registered.contains(&self.sha256) {
+            return Some(RevisionMatch {
+                registered_length: total_length,
+                sha256: self.sha256.clone(),
+                appended_revisions: 0,
+                trailing_bytes: 0,
+            });
+        }
+        self.revisions
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(index, revision)| {
+                let (sha256, registered_length) = if registered.contains(&revision.sha256) {
+                    (revision.sha256.clone(), revision.end)
+                } else {
+                    let before_eol = revision
+                        .sha256_before_eol
+                        .as_ref()
+                        .filter(|digest| registered.contains(digest))?;
+                    (before_eol.clone(), revision.marker_end)
+                };
+                let appended_revisions = self.revisions.len() - index - 1;
+                let trailing_bytes = if appended_revisions == 0 {
+                    total_length - registered_length
+                } else {
+                    self.trailing_bytes
+                };
+                Some(RevisionMatch {
+                    registered_length,
+                    sha256,
+                    appended_revisions,
+                    trailing_bytes,
+                })
+            })
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Parses `startxref <offset>` at the end of `data`, allowing whitespace around the offset.
+fn startxref_before(data: &[u8]) -> Option<u64> {
+    let data = data.trim_ascii_end();
+    let digits_start = data
+        .iter()
+        .rposition(|b| !b.is_ascii_digit())
+        .map_or(0, |i| i + 1);
+    let digits = &data[digits_start..];
+    if digits.is_empty() || digits.len() > 19 {
+        return None;
+    }
+    let before = data[..digits_start].trim_ascii_end();
+    if !before.ends_with(STARTXREF) || before.len() == digits_start {
+        return None;
+    }
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &[u8] =
+        b"%PDF-1.7\n1 0 obj\n<< >>\nendobj\nxref\ntrailer\n<< >>\nstartxref\n30\n%%EOF\n";
+    const UPDATE: &[u8] =
+        b"2 0 obj\n<< >>\nendobj\nxref\ntrailer\n<< /Prev 30 >>\nstartxref\n  120 \r\n%%EOF\r\n";
+
+    fn sha256(data: &[u8]) -> Digest {
+        Algorithm::Sha256.digest(data)
+    }
+
+    #[test]
+    fn each_revision_hashes_the_file_up_to_its_end() {
+        let data = [ORIGINAL, UPDATE].concat();
+        let analysis = analyze(&data);
+        assert_eq!(analysis.revisions.len(), 2);
+        assert_eq!(analysis.appended_revisions(), 1);
+        assert_eq!(analysis.trailing_bytes, 0);
+        assert_eq!(analysis.sha256, sha256(&data));
+
+        let [original, update] = [&analysis.revisions[0], &analysis.revisions[1]];
+        assert_eq!(
+            (original.end, original.marker_end, original.startxref),
+            (ORIGINAL.len(), ORIGINAL.len() - 1, 30)
+        );
+        assert_eq!(original.sha256, sha256(ORIGINAL));
+        assert_eq!(
+            original.sha256_before_eol,
+            Some(sha256(&ORIGINAL[..ORIGINAL.len() - 1]))
+        );
+        assert_eq!(
+            (update.end, update.marker_end, update.startxref),
+            (data.len(), data.len() - 2, 120)
+        );
+        assert_eq!(update.sha256, sha256(&data));
+    }
+
+    #[test]
+    fn markers_without_a_usable_startxref_are_skipped() {
+        // `%%EOF` inside a stream, and the `startxref 0` that ends a linearized file's first-page section.
+        let stream = b"3 0 obj\n<< /Length 6 >>\nstream\n%%EOF\nendstream\nendobj\n";
+        let linearized = b"startxref\n0\n%%EOF\n";
+        let data = [&linearized[..], stream, ORIGINAL].concat();
+        let analysis = analyze(&data);
+        assert_eq!(analysis.revisions.len(), 1);
+        assert_eq!(analysis.revisions[0].end, data.len());
+        assert!(
+            analyze(b"startxref\n%%EOF\nstartxref\nx1\n%%EOF")
+                .revisions
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn trailing_bytes_are_counted() {
+        let data = [ORIGINAL, b"junk"].concat();
+        let analysis = analyze(&data);
+        assert_eq!(analysis.revisions.len(), 1);
+        assert_eq!(analysis.trailing_bytes, 4);
+        assert_eq!(analyze(b"not a pdf").trailing_bytes, 9);
+    }
+
+    #[test]
+    fn registered_file_is_found_as_a_prefix() {
+        let data = [ORIGINAL, UPDATE, b"junk"].concat();
+        let analysis = analyze(&data);
+
+        let found = analysis
+            .find_registered(&[sha256(b"other"), sha256(ORIGINAL)])
+            .unwrap();
+        assert_eq!(
+            (
+                found.registered_length,
+                found.appended_revisions,
+                found.trailing_bytes
+            ),
+            (ORIGINAL.len(), 1, 4)
+        );
+        assert_eq!(
+            found.describe(),
+            "original plus 1 appended revision and 4 trailing bytes"
+        );
+        assert!(!found.is_exact());
+
+        let with_update = &data[..ORIGINAL.len() + UPDATE.len()];
+        let found = analysis
+            .find_registered(&[sha256(ORIGINAL), sha256(with_update)])
+            .unwrap();
+        assert_eq!(
+            (
+                found.registered_length,
+                found.appended_revisions,
+                found.trailing_bytes
+            ),
+            (with_update.len(), 0, 4)
+        );
+        assert_eq!(found.describe(), "original plus 4 trailing bytes");
+
+        let found = analysis.find_registered(&[sha256(&data)]).unwrap();
+        assert!(found.is_exact());
+        assert_eq!(found.describe(), "original");
+
+        assert_eq!(analysis.find_registered(&[sha256(b"other")]), None);
+    }
+
+    #[test]
+    fn registered_file_saved_without_final_end_of_line_is_found() {
+        let registered = &ORIGINAL[..ORIGINAL.len() - 1];
+        let data = [ORIGINAL, UPDATE].concat();
+        let found = analyze(&data)
+            .find_registered(&[sha256(registered)])
+            .unwrap();
+        assert_eq!(found.registered_length, registered.len());
+        assert_eq!(found.sha256, sha256(registered));
+        assert_eq!(found.appended_revisions, 1);
+    }
+}
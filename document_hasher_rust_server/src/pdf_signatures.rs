@@ -0,0 +1,444 @@
+//! Digital signatures embedded in a PDF (PAdES and the older Adobe PKCS#7 signatures), so a registration can
+//! say who signed a document and whether the signature still holds.
+//!
+//! A signature dictionary's `/ByteRange [a b c d]` names the signed bytes: `b` bytes from offset `a` and `d`
+//! bytes from offset `c`. The gap between them must hold exactly the `/Contents` hex string: the DER of a CMS
+//! `SignedData`, zero-padded. The CMS is read from that gap in the file itself, not from the parsed document,
+//! so it is the signature that covers those bytes. Detached signatures (`adbe.pkcs7.detached`,
+//! `ETSI.CAdES.detached`) are checked by `pkcs7` over the signed bytes; document timestamps (`ETSI.RFC3161`)
+//! are checked by `timestamp`, with the imprint the digest of the signed bytes.
+//!
+//! A signer's certificate is checked at the current time unless the signature carries a signature timestamp
+//! (the CAdES `signatureTimeStampToken` unsigned attribute, as in PAdES B-T): a token from a trusted TSA over
+//! the signature value proves the signature existed at its genTime, so the certificate is checked then.
+//!
+//! A signature covers the whole file if its ranges start at the first byte and end at the last. Otherwise
+//! the file was changed after signing, usually by an incremental update appended after the signed revision.
+
+use crate::pkcs7::{self, SignerVerification, TrustStore};
+use crate::timestamp::TimestampToken;
+use crate::{HasherError, pdf};
+use cms::content_info::ContentInfo;
+use cms::signed_data::SignedData;
+use der::asn1::ObjectIdentifier;
+use der::{Decode, Encode, Reader, SliceReader};
+use lopdf::{Dictionary, Object};
+use std::collections::HashSet;
+
+const DOCUMENT_TIMESTAMP: &[u8] = b"ETSI.RFC3161";
+const DETACHED_SUB_FILTERS: [&[u8]; 2] = [b"adbe.pkcs7.detached", b"ETSI.CAdES.detached"];
+const ID_AA_SIGNATURE_TIME_STAMP_TOKEN: ObjectIdentifier =
+    ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.16.2.14");
+
+/// One embedded signature and the outcome of checking it. It is only trustworthy if `is_valid()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PdfSignature {
+    /// The signature dictionary's `/SubFilter`, e.g. `ETSI.CAdES.detached`.
+    pub sub_filter: String,
+    /// All zero, like `signed_length`, if the `/ByteRange` is malformed; `error` then says why.
+    pub byte_range: [usize; 4],
+    /// Length of the file as signed: the end of the second byte range.
+    pub signed_length: usize,
+    /// Whether the signed bytes are the whole file, less the signature itself.
+    pub covers_whole_file: bool,
+    /// The signature dictionary's `/Name` and `/Reason`, as the signing application recorded them.
+    pub name: Option<String>,
+    pub reason: Option<String>,
+    /// The signer, if the signature could be checked. For a document timestamp this is the TSA, and
+    /// `message_digest_valid` also requires the token's imprint to match the signed bytes.
+    pub signer: Option<SignerVerification>,
+    /// The time asserted by a document timestamp, or by the signature timestamp of a signature that has a
+    /// valid one, in Unix seconds.
+    pub timestamp: Option<u64>,
+    /// Why the signature could not be checked, if it couldn't.
+    pub error: Option<String>,
+}
+
+impl PdfSignature {
+    pub fn is_valid(&self) -> bool {
+        self.error.is_none()
+            && self
+                .signer
+                .as_ref()
+                .is_some_and(SignerVerification::is_valid)
+    }
+}
+
+fn malformed(reason: impl std::fmt::Display) -> HasherError {
+    HasherError::InvalidDigest(format!("Malformed PDF signature: {}", reason))
+}
+
+/// Finds and checks every signature in the PDF in `data`, in the order of the revisions they sign.
+/// Certificates are checked at `validation_time` (Unix seconds), by default the time of a valid signature
+/// timestamp or else the current time.
+/// A signature that cannot be checked, even for a malformed `/ByteRange`, is reported with an `error`; only an
+/// unreadable PDF is an error.
+pub fn verify_signatures(
+    data: &[u8],
+    trust: &TrustStore,
+    validation_time: Option<u64>,
+) -> Result<Vec<PdfSignature>, HasherError> {
+    let document = pdf::load(data)?;
+    let mut dictionaries: Vec<&Dictionary> = Vec::new();
+    for object in document.objects.values() {
+        let Ok(dictionary) = object.as_dict() else {
+            continue;
+        };
+        dictionaries.push(dictionary);
+        // Some writers put the signature dictionary directly in the field's `/V`.
+        if let Ok(Object::Dictionary(value)) = dictionary.get(b"V") {
+            dictionaries.push(value);
+        }
+    }
+
+    let mut signatures = Vec::new();
+    let mut byte_ranges = HashSet::new();
+    for dictionary in dictionaries {
+        let Ok(byte_range) = dictionary.get(b"ByteRange").and_then(Object::as_array) else {
+            continue;
+        };
+        let text = |key: &[u8]| {
+            dictionary
+                .get(key)
+                .ok()
+                .and_then(|value| lopdf::decode_text_string(value).ok())
+        };
+        let sub_filter = dictionary
+            .get(b"SubFilter")
+            .and_then(Object::as_name)
+            .unwrap_or_default();
+        let mut signature = PdfSignature {
+            sub_filter: String::from_utf8_lossy(sub_filter).into_owned(),
+            byte_range: [0; 4],
+            signed_length: 0,
+            covers_whole_file: false,
+            name: text(b"Name"),
+            reason: text(b"Reason"),
+            signer: None,
+            timestamp: None,
+            error: None,
+        };
+        match parse_byte_range(byte_range, data.len()) {
+            Ok(byte_range) => {
+                if !byte_ranges.insert(byte_range) {
+                    continue;
+                }
+                let [a, b, c, d] = byte_range;
+                signature.byte_range = byte_range;
+                signature.signed_length = c + d;
+                signature.covers_whole_file = a == 0 && c + d == data.len();
+                let signed = [&data[a..a + b], &data[c..c + d]].concat();
+                if let Err(e) = check(
+                    &mut signature,
+                    sub_filter,
+                    &data[a + b..c],
+                    &signed,
+                    trust,
+                    validation_time,
+                ) {
+                    signature.error = Some(e.to_string());
+                }
+            }
+            Err(e) => signature.error = Some(e.to_string()),
+        }
+        signatures.push(signature);
+    }
+    signatures.sort_by_key(|signature| (signature.signed_length, signature.byte_range));
+    Ok(signatures)
+}
+
+/// Reads `/ByteRange [a b c d]`, which must name two ordered ranges within the file.
+fn parse_byte_range(items: &[Object], file_length: usize) -> Result<[usize; 4], HasherError> {
+    let values = items
+        .iter()
+        .map(|item| {
+            item.as_i64()
+                .ok()
+                .and_then(|value| usize::try_from(value).ok())
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| malformed("/ByteRange must hold non-negative integers"))?;
+    let [a, b, c, d]: [usize; 4] = values
+        .try_into()
+        .map_err(|_| malformed("/ByteRange must hold four integers"))?;
+    let in_order = a.checked_add(b).is_some_and(|first_end| first_end <= c);
+    let in_file = c
+        .checked_add(d)
+        .is_some_and(|second_end| second_end <= file_length);
+    if !in_order || !in_file {
+        return Err(malformed(format!(
+            "/ByteRange [{} {} {} {}] does not fit a file of {} bytes",
+            a, b, c, d, file_length
+        )));
+    }
+    Ok([a, b, c, d])
+}
+
+fn check(
+    signature: &mut PdfSignature,
+    sub_filter: &[u8],
+    gap: &[u8],
+    signed: &[u8],
+    trust: &TrustStore,
+    validation_time: Option<u64>,
+) -> Result<(), HasherError> {
+    let cms = contents(gap)?;
+    if sub_filter == DOCUMENT_TIMESTAMP {
+        let token = TimestampToken::from_der(&cms)?;
+        let digest = token.message_imprint()?.algorithm().digest(signed);
+        let mut verification = token.verify(&digest, None, trust, validation_time)?;
+        verification.signer.message_digest_valid &= verification.imprint_matches;
+        signature.signer = Some(verification.signer);
+        signature.timestamp = Some(token.gen_time());
+        return Ok(());
+    }
+    if !DETACHED_SUB_FILTERS.contains(&sub_filter) {
+        return Err(HasherError::UnsupportedAlgorithm(format!(
+            "PDF signatures with /SubFilter /{} are not supported",
+            signature.sub_filter
+        )));
+    }
+    let content_info = ContentInfo::from_der(&cms).map_err(malformed)?;
+    let signed_data: SignedData = content_info.content.decode_as().map_err(malformed)?;
+    if signed_data.encap_content_info.econtent.is_some() {
+        return Err(malformed(
+            "a detached signature must not encapsulate its content",
+        ));
+    }
+    signature.timestamp = signature_timestamp(&signed_data, trust);
+    let validation_time = validation_time.or(signature.timestamp);
+    signature.signer = Some(pkcs7::verify_signed_data(
+        &signed_data,
+        signed,
+        trust,
+        validation_time,
+        None,
+    )?);
+    Ok(())
+}
+
+/// The genTime of the signer's signature timestamp, if it has one that verifies against `trust` and whose
+/// imprint is the digest of the signature value. Any other token is ignored, as if there were none.
+fn signature_timestamp(signed_data: &SignedData, trust: &TrustStore) -> Option<u64> {
+    let [signer] = signed_data.signer_infos.0.as_slice() else {
+        return None;
+    };
+    let attribute = signer
+        .unsigned_attrs
+        .iter()
+        .flat_map(|set| set.iter())
+        .find(|attribute| attribute.oid == ID_AA_SIGNATURE_TIME_STAMP_TOKEN)?;
+    let [value] = attribute.values.as_slice() else {
+        return None;
+    };
+    let token = TimestampToken::from_der(&value.to_der().ok()?).ok()?;
+    let digest = token
+        .message_imprint()
+        .ok()?
+        .algorithm()
+        .digest(signer.signature.as_bytes());
+    token
+        .verify(&digest, None, trust, None)
+        .ok()?
+        .is_valid()
+        .then(|| token.gen_time())
+}
+
+/// Decodes the `/Contents` hex string that fills the gap between the byte ranges, and returns the DER
+/// `ContentInfo` at its start without the zero padding.
+fn contents(gap: &[u8]) -> Result<Vec<u8>, HasherError> {
+    let hex_digits = gap
+        .strip_prefix(b"<")
+        .and_then(|rest| rest.strip_suffix(b">"))
+        .filter(|digits| digits.iter().all(u8::is_ascii_hexdigit))
+        .ok_or_else(|| {
+            malformed("the gap between the byte ranges holds more than the /Contents hex string")
+        })?;
+    let bytes = hex::decode(hex_digits).map_err(malformed)?;
+    let mut reader = SliceReader::new(&bytes).map_err(malformed)?;
+    ContentInfo::decode(&mut reader).map_err(malformed)?;
+    let length = usize::try_from(reader.position()).map_err(malformed)?;
+    Ok(bytes[..length].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One-page PDFs with a CAdES signature by a test certificate that expired two minutes after signing.
+    /// The timestamped one also carries a signature timestamp from the test TSA.
+    const SIGNED: &[u8] = include_bytes!("../testdata/signed.pdf");
+    const SIGNED_TIMESTAMPED: &[u8] = include_bytes!("../testdata/signed-timestamped.pdf");
+    /// `SIGNED_TIMESTAMPED` with its signature timestamp from a TSA whose certificate expired two minutes later.
+    const SIGNED_TIMESTAMPED_EXPIRED_TSA: &[u8] =
+        include_bytes!("../testdata/signed-timestamped-expired-tsa.pdf");
+    /// `SIGNED_TIMESTAMPED` plus an incremental update adding a signature dictionary with a malformed `/ByteRange`.
+    const SIGNED_BAD_BYTE_RANGE: &[u8] = include_bytes!("../testdata/signed-bad-byte-range.pdf");
+    const ROOT: &str = include_str!("../testdata/root.pem");
+    const OTHER_ROOT: &str = include_str!("../testdata/other.pem");
+    /// When the fixtures were signed, in Unix seconds; the signer certificate was valid then.
+    const SIGNED_AT: u64 = 1_792_125_002;
+
+    fn trust() -> TrustStore {
+        TrustStore::from_pem(ROOT).unwrap()
+    }
+
+    fn integers(values: &[i64]) -> Vec<Object> {
+        values.iter().copied().map(Object::Integer).collect()
+    }
+
+    #[test]
+    fn byte_range_must_be_two_ordered_ranges_in_the_file() {
+        assert_eq!(
+            parse_byte_range(&integers(&[0, 10, 20, 5]), 25).unwrap(),
+            [0, 10, 20, 5]
+        );
+        assert_eq!(
+            parse_byte_range(&integers(&[0, 10, 10, 0]), 10).unwrap(),
+            [0, 10, 10, 0]
+        );
+        let rejected: [(&[i64], usize); 6] = [
+            (&[0, 10, 20], 25),
+            (&[0, 10, 20, 5, 0], 25),
+            (&[0, -1, 20, 5], 25),
+            (&[0, 21, 20, 5], 25),
+            (&[0, 10, 20, 6], 25),
+            (&[0, i64::MAX, i64::MAX, i64::MAX], 25),
+        ];
+        for (values, file_length) in rejected {
+            assert!(
+                matches!(
+                    parse_byte_range(&integers(values), file_length),
+                    Err(HasherError::InvalidDigest(_))
+                ),
+                "{:?}",
+                values
+            );
+        }
+        let mixed = vec![
+            Object::Integer(0),
+            Object::Real(10.0),
+            Object::Integer(20),
+            Object::Integer(5),
+        ];
+        assert!(parse_byte_range(&mixed, 25).is_err());
+    }
+
+    #[test]
+    fn contents_are_trimmed_to_the_content_info() {
+        // A `ContentInfo` of type id-data with empty content, then zero padding.
+        let der = hex::decode("300f06092a864886f70d010701a0020400").unwrap();
+        assert_eq!(
+            contents(b"<300f06092a864886f70d010701a002040000000000>").unwrap(),
+            der
+        );
+        assert!(contents(b"<300f06092a864886f70d010701a0020400> ").is_err());
+        assert!(contents(b"<300f06092a864886f70d010701a00204000g>").is_err());
+        assert!(contents(b"<3003020100>").is_err());
+    }
+
+    #[test]
+    fn signature_is_checked_at_the_current_time_not_its_signing_time() {
+        let [signature] = verify_signatures(SIGNED, &trust(), None)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(signature.sub_filter, "ETSI.CAdES.detached");
+        assert!(signature.covers_whole_file);
+        assert_eq!(signature.signed_length, SIGNED.len());
+        assert_eq!(signature.name.as_deref(), Some("Alice Signer"));
+        assert_eq!(signature.timestamp, None);
+        let signer = signature.signer.as_ref().unwrap();
+        assert_eq!(signer.signing_time, Some(SIGNED_AT));
+        assert!(signer.message_digest_valid && signer.signature_valid);
+        assert!(!signer.certificate_trusted, "the certificate has expired");
+        assert!(!signature.is_valid());
+
+        let [signature] = verify_signatures(SIGNED, &trust(), Some(SIGNED_AT))
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert!(signature.is_valid(), "{:?}", signature);
+    }
+
+    #[test]
+    fn signature_timestamp_sets_the_validation_time() {
+        let [signature] = verify_signatures(SIGNED_TIMESTAMPED, &trust(), None)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert!(signature.is_valid(), "{:?}", signature);
+        assert!(signature.timestamp.is_some_and(|time| time >= SIGNED_AT));
+
+        let later = SIGNED_AT + 365 * 24 * 60 * 60;
+        let [signature] = verify_signatures(SIGNED_TIMESTAMPED, &trust(), Some(later))
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert!(!signature.is_valid());
+    }
+
+    #[test]
+    fn signature_timestamp_from_an_expired_tsa_is_ignored() {
+        let [signature] = verify_signatures(SIGNED_TIMESTAMPED_EXPIRED_TSA, &trust(), None)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(signature.timestamp, None, "the TSA certificate has expired");
+        assert!(
+            signature
+                .signer
+                .as_ref()
+                .is_some_and(|signer| signer.signature_valid && !signer.certificate_trusted)
+        );
+        assert!(!signature.is_valid());
+    }
+
+    #[test]
+    fn untrusted_or_altered_signature_is_not_valid() {
+        let other = TrustStore::from_pem(OTHER_ROOT).unwrap();
+        let [signature] = verify_signatures(SIGNED_TIMESTAMPED, &other, None)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert!(!signature.signer.unwrap().certificate_trusted);
+        assert_eq!(
+            signature.timestamp, None,
+            "the signature timestamp is from an untrusted TSA too"
+        );
+
+        let mut altered = SIGNED_TIMESTAMPED.to_vec();
+        let at = altered
+            .windows(7)
+            .position(|window| window == b"(hello)")
+            .unwrap();
+        altered[at + 1] = b'j';
+        let [signature] = verify_signatures(&altered, &trust(), Some(SIGNED_AT))
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert!(!signature.signer.unwrap().message_digest_valid);
+    }
+
+    #[test]
+    fn malformed_byte_range_is_reported_on_its_signature() {
+        let signatures = verify_signatures(SIGNED_BAD_BYTE_RANGE, &trust(), None).unwrap();
+        let [malformed, signed]: [PdfSignature; 2] = signatures.try_into().unwrap();
+        assert_eq!(malformed.byte_range, [0; 4]);
+        assert!(
+            malformed
+                .error
+                .as_deref()
+                .is_some_and(|error| error.contains("/ByteRange"))
+        );
+        assert!(!malformed.is_valid());
+        assert!(signed.is_valid(), "{:?}", signed);
+        assert!(!signed.covers_whole_file);
+        assert_eq!(signed.signed_length, SIGNED_TIMESTAMPED.len());
+    }
+
+    #[test]
+    fn unreadable_pdf_is_an_error() {
+        assert!(verify_signatures(b"not a pdf", &trust(), None).is_err());
+    }
+}
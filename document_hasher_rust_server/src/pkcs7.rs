@@ -0,0 +1,524 @@
+//! Verification of CMS `SignedData` (RFC 5652, the successor of PKCS#7) against a caller-supplied set of
+//! trusted root certificates. This backs RFC 3161 timestamp tokens in `timestamp` and embedded PDF signatures in
+//! `pdf_signatures`.
+//!
+//! The `SignedData` must have exactly one `SignerInfo`: with several, reporting one would hide the others.
+//! Its certificate must be embedded in the `SignedData`. The `messageDigest` signed attribute must match the
+//! content, and the signature must verify over the DER of the signed attributes. The chain is then built
+//! through the embedded certificates to a trust anchor, and every certificate in it must be valid at the given
+//! time. Supported signatures are RSA PKCS#1 v1.5, ECDSA over P-256 and P-384, and Ed25519.
+
+use crate::ed25519::Ed25519PublicKey;
+use crate::{Algorithm, HasherError};
+use cms::cert::CertificateChoices;
+use cms::signed_data::{SignedData, SignerIdentifier, SignerInfo};
+use der::asn1::{ObjectIdentifier, OctetString};
+use der::{Decode, DecodeOwned, Encode};
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+use p256::pkcs8::DecodePublicKey;
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use sha2::{Sha256, Sha384, Sha512, Sha512_256};
+use sha3::Sha3_256;
+use std::time::{SystemTime, UNIX_EPOCH};
+use x509_cert::Certificate;
+use x509_cert::ext::pkix::{BasicConstraints, ExtendedKeyUsage, SubjectKeyIdentifier};
+use x509_cert::spki::SubjectPublicKeyInfoOwned;
+use x509_cert::time::Time;
+
+/// The longest certificate chain followed from a signer to a trust anchor.
+const MAX_CHAIN_LENGTH: usize = 8;
+
+const ID_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+const ID_SHA384: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.2");
+const ID_SHA512: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.3");
+const ID_SHA512_256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.6");
+const ID_SHA3_256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.8");
+
+const RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const SHA256_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.11");
+const SHA384_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.12");
+const SHA512_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.13");
+const ID_EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+const ECDSA_WITH_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+const ECDSA_WITH_SHA384: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.3");
+const ECDSA_WITH_SHA512: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.4");
+const SECP256R1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.3.1.7");
+const SECP384R1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.132.0.34");
+const ID_ED25519: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.112");
+
+const ID_CONTENT_TYPE: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.3");
+const ID_MESSAGE_DIGEST: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.4");
+const ID_SIGNING_TIME: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.5");
+
+/// The OID identifying `algorithm` in ASN.1 structures, or `None` for BLAKE3, which has none.
+pub(crate) fn algorithm_oid(algorithm: Algorithm) -> Option<ObjectIdentifier> {
+    match algorithm {
+        Algorithm::Sha256 => Some(ID_SHA256),
+        Algorithm::Sha384 => Some(ID_SHA384),
+        Algorithm::Sha512 => Some(ID_SHA512),
+        Algorithm::Sha512_256 => Some(ID_SHA512_256),
+        Algorithm::Sha3_256 => Some(ID_SHA3_256),
+        Algorithm::Blake3 => None,
+    }
+}
+
+/// The digest algorithm identified by `oid`.
+pub(crate) fn algorithm_from_oid(oid: &ObjectIdentifier) -> Result<Algorithm, HasherError> {
+    Algorithm::ALL
+        .into_iter()
+        .find(|&algorithm| algorithm_oid(algorithm).as_ref() == Some(oid))
+        .ok_or_else(|| {
+            HasherError::UnsupportedAlgorithm(format!("Unsupported digest algorithm OID {}", oid))
+        })
+}
+
+fn malformed(reason: impl std::fmt::Display) -> HasherError {
+    HasherError::InvalidDigest(format!("Malformed CMS signature: {}", reason))
+}
+
+/// A set of trusted root certificates.
+#[derive(Debug, Clone, Default)]
+pub struct TrustStore {
+    anchors: Vec<Certificate>,
+}
+
+impl TrustStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a store from one or more concatenated `-----BEGIN CERTIFICATE-----` PEM blocks.
+    pub fn from_pem(pem: &str) -> Result<Self, HasherError> {
+        let mut store = Self::new();
+        store.add_pem(pem)?;
+        Ok(store)
+    }
+
+    /// Adds every certificate in one or more concatenated PEM blocks.
+    pub fn add_pem(&mut self, pem: &str) -> Result<(), HasherError> {
+        let certificates = Certificate::load_pem_chain(pem.as_bytes()).map_err(|e| {
+            HasherError::InvalidKey(format!("Invalid trust anchor certificate: {}", e))
+        })?;
+        self.anchors.extend(certificates);
+        Ok(())
+    }
+
+    /// Adds a single DER-encoded certificate.
+    pub fn add_der(&mut self, der: &[u8]) -> Result<(), HasherError> {
+        let certificate = Certificate::from_der(der).map_err(|e| {
+            HasherError::InvalidKey(format!("Invalid trust anchor certificate: {}", e))
+        })?;
+        self.anchors.push(certificate);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.anchors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty()
+    }
+}
+
+/// The outcome of checking a `SignerInfo`. The signature is only trustworthy if `is_valid()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignerVerification {
+    /// The signer certificate's subject, as an RFC 4514 string.
+    pub subject: String,
+    pub issuer: String,
+    /// The signer certificate's serial number, hex-encoded.
+    pub serial_number: String,
+    pub digest_algorithm: Algorithm,
+    /// The `signingTime` signed attribute, in Unix seconds, if present.
+    pub signing_time: Option<u64>,
+    /// Whether the `messageDigest` attribute matches the content (true if there are no signed attributes,
+    /// in which case the signature covers the content directly).
+    pub message_digest_valid: bool,
+    pub signature_valid: bool,
+    /// Whether the signer certificate chains to a trust anchor and every certificate in the chain is valid.
+    pub certificate_trusted: bool,
+    /// Why the certificate is not trusted, if it isn't.
+    pub trust_error: Option<String>,
+}
+
+impl SignerVerification {
+    pub fn is_valid(&self) -> bool {
+        self.message_digest_valid && self.signature_valid && self.certificate_trusted
+    }
+}
+
+/// Verifies the signer of `signed_data` over `content` (the encapsulated content, or the detached
+/// content it signs). Certificates are checked for validity at `validation_time` (Unix seconds), by default the
+/// current time, and the signer certificate must carry `required_key_usage` in its extended key usage, if given.
+/// The `signingTime` attribute is only reported: the signer chooses it, so it cannot vouch for a certificate.
+pub(crate) fn verify_signed_data(
+    signed_data: &SignedData,
+    content: &[u8],
+    trust: &TrustStore,
+    validation_time: Option<u64>,
+    required_key_usage: Option<ObjectIdentifier>,
+) -> Result<SignerVerification, HasherError> {
+    let signer = match signed_data.signer_infos.0.as_slice() {
+        [signer] => signer,
+        [] => return Err(malformed("no SignerInfo")),
+        signers => {
+            return Err(malformed(format!(
+                "{} SignerInfos; only single-signer SignedData is supported",
+                signers.len()
+            )));
+        }
+    };
+    let certificates: Vec<&Certificate> = signed_data
+        .certificates
+        .iter()
+        .flat_map(|set| set.0.iter())
+        .filter_map(|choice| match choice {
+            CertificateChoices::Certificate(certificate) => Some(certificate),
+            CertificateChoices::Other(_) => None,
+        })
+        .collect();
+    let certificate = certificates
+        .iter()
+        .copied()
+        .find(|certificate| identifies(&signer.sid, certificate))
+        .ok_or_else(|| malformed("the signer certificate is not embedded"))?;
+    let digest_algorithm = algorithm_from_oid(&signer.digest_alg.oid)?;
+
+    let (message_digest_valid, signed_message, signing_time) = match &signer.signed_attrs {
+        Some(attributes) => {
+            let message_digest: OctetString = single_attribute(signer, &ID_MESSAGE_DIGEST)?
+                .ok_or_else(|| malformed("missing messageDigest attribute"))?;
+            let content_type: ObjectIdentifier = single_attribute(signer, &ID_CONTENT_TYPE)?
+                .ok_or_else(|| malformed("missing contentType attribute"))?;
+            if content_type != signed_data.encap_content_info.econtent_type {
+                return Err(malformed(
+                    "the contentType attribute does not match the content",
+                ));
+            }
+            let signing_time: Option<Time> = single_attribute(signer, &ID_SIGNING_TIME)?;
+            let computed = digest_algorithm.digest(content);
+            (
+                message_digest.as_bytes() == computed.as_bytes(),
+                attributes.to_der().map_err(malformed)?,
+                signing_time.map(|time| time.to_unix_duration().as_secs()),
+            )
+        }
+        None => (true, content.to_vec(), None),
+    };
+    let signature_valid = verify_signature(
+        &certificate.tbs_certificate.subject_public_key_info,
+        &signer.signature_algorithm.oid,
+        Some(digest_algorithm),
+        &signed_message,
+        signer.signature.as_bytes(),
+    )?;
+
+    let trust_result = match required_key_usage {
+        Some(usage) if !has_extended_key_usage(certificate, &usage) => {
+            Err(format!("the signer certificate is not valid for {}", usage))
+        }
+        _ => verify_chain(
+            certificate,
+            &certificates,
+            trust,
+            validation_time.unwrap_or_else(now),
+        ),
+    };
+    let tbs = &certificate.tbs_certificate;
+    Ok(SignerVerification {
+        subject: tbs.subject.to_string(),
+        issuer: tbs.issuer.to_string(),
+        serial_number: hex::encode(tbs.serial_number.as_bytes()),
+        digest_algorithm,
+        signing_time,
+        message_digest_valid,
+        signature_valid,
+        certificate_trusted: trust_result.is_ok(),
+        trust_error: trust_result.err(),
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+fn identifies(sid: &SignerIdentifier, certificate: &Certificate) -> bool {
+    let tbs = &certificate.tbs_certificate;
+    match sid {
+        SignerIdentifier::IssuerAndSerialNumber(id) => {
+            id.issuer == tbs.issuer && id.serial_number == tbs.serial_number
+        }
+        SignerIdentifier::SubjectKeyIdentifier(id) => {
+            matches!(tbs.get::<SubjectKeyIdentifier>(), Ok(Some((_, ski))) if ski == *id)
+        }
+    }
+}
+
+/// Decodes the value of the signed attribute `oid`, which must have exactly one value if present.
+fn single_attribute<T: DecodeOwned>(
+    signer: &SignerInfo,
+    oid: &ObjectIdentifier,
+) -> Result<Option<T>, HasherError> {
+    let mut matching = signer
+        .signed_attrs
+        .iter()
+        .flat_map(|set| set.iter())
+        .filter(|a| a.oid == *oid);
+    let Some(attribute) = matching.next() else {
+        return Ok(None);
+    };
+    if matching.next().is_some() || attribute.values.len() != 1 {
+        return Err(malformed(format!(
+            "attribute {} must have exactly one value",
+            oid
+        )));
+    }
+    let value = attribute
+        .values
+        .get(0)
+        .ok_or_else(|| malformed(format!("attribute {} is empty", oid)))?;
+    let value = value.to_der().map_err(malformed)?;
+    T::from_der(&value)
+        .map(Some)
+        .map_err(|e| malformed(format!("attribute {}: {}", oid, e)))
+}
+
+fn has_extended_key_usage(certificate: &Certificate, usage: &ObjectIdentifier) -> bool {
+    matches!(certificate.tbs_certificate.get::<ExtendedKeyUsage>(), Ok(Some((_, eku))) if eku.0.contains(usage))
+}
+
+fn is_ca(certificate: &Certificate) -> bool {
+    matches!(certificate.tbs_certificate.get::<BasicConstraints>(), Ok(Some((_, constraints))) if constraints.ca)
+}
+
+fn check_validity(certificate: &Certificate, time: u64) -> Result<(), String> {
+    let validity = &certificate.tbs_certificate.validity;
+    let not_before = validity.not_before.to_unix_duration().as_secs();
+    let not_after = validity.not_after.to_unix_duration().as_secs();
+    if time < not_before || time > not_after {
+        return Err(format!(
+            "certificate '{}' is not valid at {} (valid {} to {})",
+            certificate.tbs_certificate.subject, time, validity.not_before, validity.not_after
+        ));
+    }
+    Ok(())
+}
+
+/// Whether `issuer` signed `certificate`.
+fn issued_by(certificate: &Certificate, issuer: &Certificate) -> bool {
+    if certificate.tbs_certificate.issuer != issuer.tbs_certificate.subject {
+        return false;
+    }
+    let Ok(tbs) = certificate.tbs_certificate.to_der() else {
+        return false;
+    };
+    let Some(signature) = certificate.signature.as_bytes() else {
+        return false;
+    };
+    let spki = &issuer.tbs_certificate.subject_public_key_info;
+    verify_signature(
+        spki,
+        &certificate.signature_algorithm.oid,
+        None,
+        &tbs,
+        signature,
+    )
+    .unwrap_or(false)
+}
+
+/// Follows issuers from `leaf` through `intermediates` until reaching a certificate in `trust` or one
+/// issued by it.
+fn verify_chain(
+    leaf: &Certificate,
+    intermediates: &[&Certificate],
+    trust: &TrustStore,
+    time: u64,
+) -> Result<(), String> {
+    let mut current = leaf;
+    for _ in 0..MAX_CHAIN_LENGTH {
+        check_validity(current, time)?;
+        if trust.anchors.contains(current) {
+            return Ok(());
+        }
+        if let Some(anchor) = trust
+            .anchors
+            .iter()
+            .find(|anchor| issued_by(current, anchor))
+        {
+            return check_validity(anchor, time);
+        }
+        current = intermediates
+            .iter()
+            .copied()
+            .find(|candidate| {
+                *candidate != current && is_ca(candidate) && issued_by(current, candidate)
+            })
+            .ok_or_else(|| {
+                format!(
+                    "no trusted issuer found for certificate '{}'",
+                    current.tbs_certificate.subject
+                )
+            })?;
+    }
+    Err(format!(
+        "certificate chain is longer than {} certificates",
+        MAX_CHAIN_LENGTH
+    ))
+}
+
+/// Verifies `signature` over `message` with the public key in `spki`. The hash is implied by
+/// `signature_algorithm` where it names one, and is `digest_algorithm` where it names only the key type.
+/// A signature algorithm that does not match the key type does not verify.
+fn verify_signature(
+    spki: &SubjectPublicKeyInfoOwned,
+    signature_algorithm: &ObjectIdentifier,
+    digest_algorithm: Option<Algorithm>,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<bool, HasherError> {
+    let unsupported = || {
+        HasherError::UnsupportedAlgorithm(format!(
+            "Unsupported signature algorithm OID {}",
+            signature_algorithm
+        ))
+    };
+    let spki_der = spki
+        .to_der()
+        .map_err(|e| malformed(format!("public key: {}", e)))?;
+    let key_error = |e: &dyn std::fmt::Display| {
+        HasherError::InvalidKey(format!("Invalid certificate public key: {}", e))
+    };
+
+    if *signature_algorithm == ID_ED25519 || spki.algorithm.oid == ID_ED25519 {
+        if *signature_algorithm != spki.algorithm.oid {
+            return Ok(false);
+        }
+        return Ed25519PublicKey::from_public_key_der(&spki_der)?
+            .verify(message, signature)
+            .or(Ok(false));
+    }
+    let hash = match *signature_algorithm {
+        SHA256_WITH_RSA | ECDSA_WITH_SHA256 => Algorithm::Sha256,
+        SHA384_WITH_RSA | ECDSA_WITH_SHA384 => Algorithm::Sha384,
+        SHA512_WITH_RSA | ECDSA_WITH_SHA512 => Algorithm::Sha512,
+        RSA_ENCRYPTION | ID_EC_PUBLIC_KEY => digest_algorithm.ok_or_else(unsupported)?,
+        _ => return Err(unsupported()),
+    };
+    let hashed = hash.digest(message);
+
+    if spki.algorithm.oid == RSA_ENCRYPTION {
+        if !matches!(
+            *signature_algorithm,
+            RSA_ENCRYPTION | SHA256_WITH_RSA | SHA384_WITH_RSA | SHA512_WITH_RSA
+        ) {
+            return Ok(false);
+        }
+        let key = RsaPublicKey::from_public_key_der(&spki_der).map_err(|e| key_error(&e))?;
+        let scheme = match hash {
+            Algorithm::Sha256 => Pkcs1v15Sign::new::<Sha256>(),
+            Algorithm::Sha384 => Pkcs1v15Sign::new::<Sha384>(),
+            Algorithm::Sha512 => Pkcs1v15Sign::new::<Sha512>(),
+            Algorithm::Sha512_256 => Pkcs1v15Sign::new::<Sha512_256>(),
+            Algorithm::Sha3_256 => Pkcs1v15Sign::new::<Sha3_256>(),
+            Algorithm::Blake3 => return Err(unsupported()),
+        };
+        return Ok(key.verify(scheme, hashed.as_bytes(), signature).is_ok());
+    }
+    if spki.algorithm.oid == ID_EC_PUBLIC_KEY {
+        if !matches!(
+            *signature_algorithm,
+            ID_EC_PUBLIC_KEY | ECDSA_WITH_SHA256 | ECDSA_WITH_SHA384 | ECDSA_WITH_SHA512
+        ) {
+            return Ok(false);
+        }
+        let curve = spki
+            .algorithm
+            .parameters
+            .as_ref()
+            .and_then(|p| p.decode_as::<ObjectIdentifier>().ok());
+        return match curve {
+            Some(SECP256R1) => {
+                let key = p256::ecdsa::VerifyingKey::from_public_key_der(&spki_der)
+                    .map_err(|e| key_error(&e))?;
+                let Ok(signature) = p256::ecdsa::Signature::from_der(signature) else {
+                    return Ok(false);
+                };
+                Ok(key.verify_prehash(hashed.as_bytes(), &signature).is_ok())
+            }
+            Some(SECP384R1) => {
+                let key = p384::ecdsa::VerifyingKey::from_public_key_der(&spki_der)
+                    .map_err(|e| key_error(&e))?;
+                let Ok(signature) = p384::ecdsa::Signature::from_der(signature) else {
+                    return Ok(false);
+                };
+                Ok(key.verify_prehash(hashed.as_bytes(), &signature).is_ok())
+            }
+            _ => Err(HasherError::UnsupportedAlgorithm(
+                "Unsupported elliptic curve".to_string(),
+            )),
+        };
+    }
+    Err(HasherError::UnsupportedAlgorithm(format!(
+        "Unsupported public key algorithm OID {}",
+        spki.algorithm.oid
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecdsa_p256::EcdsaP256PrivateKey;
+    use crate::ed25519::Ed25519PrivateKey;
+
+    const MESSAGE: &[u8] = b"signed attributes";
+
+    fn spki(der: &[u8]) -> SubjectPublicKeyInfoOwned {
+        SubjectPublicKeyInfoOwned::from_der(der).unwrap()
+    }
+
+    fn ecdsa_der_signature(key: &EcdsaP256PrivateKey, message: &[u8]) -> Vec<u8> {
+        let signature = p256::ecdsa::Signature::from_slice(&key.sign(message)).unwrap();
+        signature.to_der().as_bytes().to_vec()
+    }
+
+    #[test]
+    fn signatures_verify_under_the_matching_key() {
+        let ed25519 = Ed25519PrivateKey::generate();
+        let ed25519_spki = spki(&ed25519.public_key().to_public_key_der().unwrap());
+        let signature = ed25519.sign(MESSAGE);
+        assert!(verify_signature(&ed25519_spki, &ID_ED25519, None, MESSAGE, &signature).unwrap());
+        assert!(!verify_signature(&ed25519_spki, &ID_ED25519, None, b"other", &signature).unwrap());
+
+        let p256 = EcdsaP256PrivateKey::generate();
+        let p256_spki = spki(&p256.public_key().to_public_key_der().unwrap());
+        let signature = ecdsa_der_signature(&p256, MESSAGE);
+        assert!(
+            verify_signature(&p256_spki, &ECDSA_WITH_SHA256, None, MESSAGE, &signature).unwrap()
+        );
+    }
+
+    #[test]
+    fn ed25519_signature_under_another_key_type_does_not_verify() {
+        let ed25519 = Ed25519PrivateKey::generate();
+        let p256 = EcdsaP256PrivateKey::generate();
+        let p256_spki = spki(&p256.public_key().to_public_key_der().unwrap());
+        let signature = ed25519.sign(MESSAGE);
+        assert!(!verify_signature(&p256_spki, &ID_ED25519, None, MESSAGE, &signature).unwrap());
+    }
+
+    #[test]
+    fn other_signature_algorithm_under_an_ed25519_key_does_not_verify() {
+        let ed25519 = Ed25519PrivateKey::generate();
+        let ed25519_spki = spki(&ed25519.public_key().to_public_key_der().unwrap());
+        let p256 = EcdsaP256PrivateKey::generate();
+        let signature = ecdsa_der_signature(&p256, MESSAGE);
+        for algorithm in [ECDSA_WITH_SHA256, SHA256_WITH_RSA] {
+            assert!(
+                !verify_signature(&ed25519_spki, &algorithm, None, MESSAGE, &signature).unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Registration receipts signed with Ed25519, verifiable offline with only the server's public key.
+//!
+//! A receipt covers the document's SHA256, its file name, the registration time (Unix seconds) and the
+//! id of the signing key. It is exchanged as JSON:
+//!
+//! ```json
+//! {"version": 1, "sha256": "<hex>", "file_name": "contract.pdf", "timestamp": 1700000000,
+//!  "key_id": "<id>", "signature": "<base64url>"}
+//! ```
+//!
+//! The signature is not over the JSON but over a fixed binary encoding of the fields: the label
+//! `hashsure-receipt-v1`, a zero byte, then each field (the raw digest, the UTF-8 file name, the
+//! timestamp as 8 big-endian bytes, the UTF-8 key id) preceded by its length as 4 big-endian bytes.
+
+use crate::ed25519::{Ed25519PrivateKey, Ed25519PublicKey, SIGNATURE_LENGTH};
+use crate::encoding::push_field;
+use crate::{Algorithm, Digest, HasherError};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use serde_json::Value;
+
+/// The receipt format version written to and required in the JSON.
+pub const RECEIPT_VERSION: u64 = 1;
+
+const SIGNING_LABEL: &[u8] = b"hashsure-receipt-v1\0";
+
+/// A signed registration receipt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Receipt {
+    pub sha256: Digest,
+    pub file_name: String,
+    pub timestamp: u64,
+    pub key_id: String,
+    signature: [u8; SIGNATURE_LENGTH],
+}
+
+fn signing_input(sha256: &Digest, file_name: &str, timestamp: u64, key_id: &str) -> Vec<u8> {
+    let mut input = SIGNING_LABEL.to_vec();
+    push_field(&mut input, sha256.as_bytes());
+    push_field(&mut input, file_name.as_bytes());
+    push_field(&mut input, &timestamp.to_be_bytes());
+    push_field(&mut input, key_id.as_bytes());
+    input
+}
+
+fn malformed(reason: impl std::fmt::Display) -> HasherError {
+    HasherError::InvalidDigest(format!("Malformed receipt: {}", reason))
+}
+
+impl Receipt {
+    /// Signs a receipt for a document. `key_id` defaults to the public key's `key_id()`.
+    pub fn issue(
+        private_key: &Ed25519PrivateKey,
+        key_id: Option<&str>,
+        sha256: Digest,
+        file_name: &str,
+        timestamp: u64,
+    ) -> Result<Self, HasherError> {
+        if sha256.algorithm() != Algorithm::Sha256 {
+            return Err(HasherError::InvalidInput(
+                "Receipts cover SHA256 digests only".to_string(),
+            ));
+        }
+        let key_id = key_id.map_or_else(|| private_key.public_key().key_id(), str::to_string);
+        let signature = private_key.sign(&signing_input(&sha256, file_name, timestamp, &key_id));
+        Ok(Receipt {
+            sha256,
+            file_name: file_name.to_string(),
+            timestamp,
+            key_id,
+            signature,
+        })
+    }
+
+    pub fn signature(&self) -> &[u8] {
+        &self.signature
+    }
+
+    /// Checks the receipt's signature with `public_key`.
+    pub fn verify(&self, public_key: &Ed25519PublicKey) -> bool {
+        let input = signing_input(&self.sha256, &self.file_name, self.timestamp, &self.key_id);
+        public_key.verify(&input, &self.signature).unwrap_or(false)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "version": RECEIPT_VERSION,
+            "sha256": self.sha256.to_hex(),
+            "file_name": self.file_name,
+            "timestamp": self.timestamp,
+            "key_id": self.key_id,
+            "signature": BASE64URL.encode(self.signature),
+        })
+        .to_string()
+    }
+
+    /// Parses a receipt from its JSON form. The signature is not checked; call `verify`.
+    pub fn from_json(json: &str) -> Result<Self, HasherError> {
+        let value: Value = serde_json::from_str(json).map_err(malformed)?;
+        let field = |name: &str| {
+            value
+                .get(name)
+                .ok_or_else(|| malformed(format!("missing '{}'", name)))
+        };
+        let string = |name: &str| {
+            field(name)?
+                .as_str()
+                .ok_or_else(|| malformed(format!("'{}' is not a string", name)))
+        };
+        let integer = |name: &str| {
+            field(name)?
+                .as_u64()
+                .ok_or_else(|| malformed(format!("'{}' is not an integer", name)))
+        };
+
+        let version = integer("version")?;
+        if version != RECEIPT_VERSION {
+            return Err(malformed(format!("unsupported version {}", version)));
+        }
+        let signature = BASE64URL.decode(string("signature")?).map_err(malformed)?;
+        let signature = signature.try_into().map_err(|s: Vec<u8>| {
+            malformed(format!(
+                "signature must be {} bytes, got {}",
+                SIGNATURE_LENGTH,
+                s.len()
+            ))
+        })?;
+        Ok(Receipt {
+            sha256: Digest::from_hex(Algorithm::Sha256, string("sha256")?)?,
+            file_name: string("file_name")?.to_string(),
+            timestamp: integer("timestamp")?,
+            key_id: string("key_id")?.to_string(),
+            signature,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn private_key() -> Ed25519PrivateKey {
+        Ed25519PrivateKey::from_raw(&[7; 32]).unwrap()
+    }
+
+    fn receipt() -> Receipt {
+        Receipt::issue(
+            &private_key(),
+            None,
+            crate::sha256(b"contract"),
+            "contract.pdf",
+            1_700_000_000,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn receipt_round_trips_through_json_and_verifies() {
+        let receipt = receipt();
+        assert_eq!(receipt.key_id, private_key().public_key().key_id());
+        let parsed = Receipt::from_json(&receipt.to_json()).unwrap();
+        assert_eq!(parsed, receipt);
+        assert!(parsed.verify(&private_key().public_key()));
+
+        let named = Receipt::issue(
+            &private_key(),
+            Some("2024-01"),
+            crate::sha256(b"contract"),
+            "contract.pdf",
+            1_700_000_000,
+        )
+        .unwrap();
+        assert_eq!(named.key_id, "2024-01");
+        assert!(named.verify(&private_key().public_key()));
+        assert_ne!(named.signature(), receipt.signature());
+    }
+
+    #[test]
+    fn other_key_does_not_verify() {
+        let other = Ed25519PrivateKey::from_raw(&[8; 32]).unwrap();
+        assert!(!receipt().verify(&other.public_key()));
+    }
+
+    #[test]
+    fn changing_any_field_breaks_the_signature() {
+        let public_key = private_key().public_key();
+        let modified = [
+            Receipt {
+                sha256: crate::sha256(b"contract2"),
+                ..receipt()
+            },
+            Receipt {
+                file_name: "contract2.pdf".to_string(),
+                ..receipt()
+            },
+            Receipt {
+                timestamp: 1_700_000_001,
+                ..receipt()
+            },
+            Receipt {
+                key_id: "other".to_string(),
+                ..receipt()
+            },
+        ];
+        for receipt in modified {
+            let parsed = Receipt::from_json(&receipt.to_json()).unwrap();
+            assert!(!parsed.verify(&public_key), "{:?}", parsed);
+        }
+    }
+
+    #[test]
+    fn malformed_json_is_rejected() {
+        let json: Value = serde_json::from_str(&receipt().to_json()).unwrap();
+        let mut wrong_version = json.clone();
+        wrong_version["version"] = 2.into();
+        let mut short_signature = json.clone();
+        short_signature["signature"] = "AAAA".into();
+        let mut missing = json.clone();
+        missing.as_object_mut().unwrap().remove("timestamp");
+        for value in [wrong_version, short_signature, missing] {
+            assert!(matches!(
+                Receipt::from_json(&value.to_string()),
+                Err(HasherError::InvalidDigest(_))
+            ));
+        }
+        assert!(
+            Receipt::issue(
+                &private_key(),
+                None,
+                Algorithm::Sha3_256.digest(b""),
+                "a",
+                0
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! Canonical encoding of a registration record, so its HMAC tag authenticates every field of the stored row
+//! rather than just the digest. Without it, a database attacker can rename or re-date a record and keep its tag.
+//!
+//! The encoding (version 1) is, in order:
+//!
+//! 1. the label `hashsure-record` followed by a zero byte (domain separation from every other MAC input);
+//! 2. the version byte `0x01`;
+//! 3. four fields, each preceded by its length as 4 big-endian bytes:
+//!    - the digest algorithm's name in ASCII (e.g. `sha256`),
+//!    - the raw digest bytes (not hex),
+//!    - the file name in UTF-8, exactly as stored,
+//!    - the upload time as microseconds since the Unix epoch (UTC), 8 big-endian bytes, two's complement.
+//!
+//! The row's surrogate `id` is not covered: it is assigned by the database after the tag is computed.
+//! Any change to the layout must bump `RECORD_VERSION`.
+
+use crate::encoding::push_field;
+use crate::{Algorithm, Digest, HasherError};
+
+/// The version byte of the canonical encoding.
+pub const RECORD_VERSION: u8 = 1;
+
+const DOMAIN_LABEL: &[u8] = b"hashsure-record\0";
+
+/// The fields of a registration record that its tag authenticates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub digest: Digest,
+    pub file_name: String,
+    /// Upload time in microseconds since the Unix epoch, UTC.
+    pub uploaded_at: i64,
+}
+
+impl Record {
+    pub fn new(digest: Digest, file_name: &str, uploaded_at: i64) -> Self {
+        Record {
+            digest,
+            file_name: file_name.to_string(),
+            uploaded_at,
+        }
+    }
+
+    /// The canonical byte encoding described in the module documentation.
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let mut out = DOMAIN_LABEL.to_vec();
+        out.push(RECORD_VERSION);
+        push_field(&mut out, self.digest.algorithm().name().as_bytes());
+        push_field(&mut out, self.digest.as_bytes());
+        push_field(&mut out, self.file_name.as_bytes());
+        push_field(&mut out, &self.uploaded_at.to_be_bytes());
+        out
+    }
+
+    /// Calculates the record's HMAC-SHA256 tag.
+    pub fn hmac_sha256(&self, secret_key: &[u8]) -> Result<Digest, HasherError> {
+        Algorithm::Sha256.hmac(secret_key, &self.canonical_bytes())
+    }
+
+    /// Verifies the record's HMAC-SHA256 tag in constant time.
+    pub fn verify_hmac_sha256(&self, secret_key: &[u8], tag: &[u8]) -> Result<bool, HasherError> {
+        Algorithm::Sha256.verify_hmac(secret_key, &self.canonical_bytes(), tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = &[b'k'; 32];
+
+    fn record() -> Record {
+        Record::new(
+            Digest::new(Algorithm::Sha256, vec![0xab; 32]),
+            "report é.pdf",
+            1_714_564_800_123_456,
+        )
+    }
+
+    #[test]
+    fn canonical_bytes_follow_the_documented_layout() {
+        let mut expected = b"hashsure-record\0\x01".to_vec();
+        expected.extend_from_slice(b"\x00\x00\x00\x06sha256");
+        expected.extend_from_slice(b"\x00\x00\x00\x20");
+        expected.extend_from_slice(&[0xab; 32]);
+        expected.extend_from_slice(b"\x00\x00\x00\x0dreport \xc3\xa9.pdf");
+        expected.extend_from_slice(b"\x00\x00\x00\x08\x00\x06\x17\x63\x39\xdb\x12\x40");
+        assert_eq!(record().canonical_bytes(), expected);
+    }
+
+    #[test]
+    fn negative_upload_times_are_twos_complement() {
+        let bytes = Record {
+            uploaded_at: -1,
+            ..record()
+        }
+        .canonical_bytes();
+        assert!(bytes.ends_with(b"\x00\x00\x00\x08\xff\xff\xff\xff\xff\xff\xff\xff"));
+    }
+
+    #[test]
+    fn tag_is_hmac_sha256_of_canonical_bytes() {
+        let tag = record().hmac_sha256(KEY).unwrap();
+        assert_eq!(
+            tag.to_hex(),
+            "8484406760dcc910d865e8b96ea2634990970fd63222810832da7860d617fd3c"
+        );
+        assert!(record().verify_hmac_sha256(KEY, tag.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn tag_covers_every_field() {
+        let tag = record().hmac_sha256(KEY).unwrap();
+        let changed = [
+            Record {
+                digest: Digest::new(Algorithm::Sha256, vec![0xac; 32]),
+                ..record()
+            },
+            Record {
+                digest: Digest::new(Algorithm::Sha3_256, vec![0xab; 32]),
+                ..record()
+            },
+            Record {
+                file_name: "report e.pdf".to_string(),
+                ..record()
+            },
+            Record {
+                uploaded_at: 1_714_564_800_123_457,
+                ..record()
+            },
+        ];
+        for record in changed {
+            assert!(
+                !record.verify_hmac_sha256(KEY, tag.as_bytes()).unwrap(),
+                "{:?}",
+                record
+            );
+        }
+        assert!(
+            !record()
+                .verify_hmac_sha256(&[b'j'; 32], tag.as_bytes())
+                .unwrap()
+        );
+    }
+}
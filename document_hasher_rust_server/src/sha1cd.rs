@@ -0,0 +1,119 @@
+//! SHA-1 with counter-cryptanalytic collision detection (Marc Stevens' sha1collisiondetection), for the legacy
+//! SHA-1 fingerprints some partner systems still send.
+//!
+//! SHA-1 is broken: SHAttered (2017) and "SHA-1 is a Shambles" (2020) produced pairs of distinct documents,
+//! PDFs among them, with equal SHA-1. Every known practical attack builds its near-collision blocks along one of
+//! a small set of disturbance vectors. While hashing, each compressed block is checked against the top 32 of
+//! them, which flags either file of a colliding pair; the chance of a false positive is below 2^-90. A flagged
+//! document's SHA-1 was chosen by an attacker and must not be trusted.
+//!
+//! SHA-1 is deliberately not an `Algorithm`: it is only checked against fingerprints supplied by others,
+//! never used for new registrations.
+
+use sha1collisiondetection::{Output, Sha1CD};
+
+/// The SHA-1 of some data, and whether the data contains collision attack blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sha1Check {
+    /// The standard SHA-1, equal to what other implementations compute even for colliding data.
+    pub sha1: [u8; 20],
+    pub collision_detected: bool,
+}
+
+impl Sha1Check {
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.sha1)
+    }
+}
+
+/// An incremental SHA-1 hasher that detects collision attacks.
+#[derive(Debug, Clone)]
+pub struct Sha1CollisionDetector {
+    hasher: Sha1CD,
+}
+
+impl Default for Sha1CollisionDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha1CollisionDetector {
+    pub fn new() -> Self {
+        // Without safe-hash mode the standard SHA-1 is returned for colliding data too, so it can still be
+        // compared with the fingerprint it is meant to match.
+        Sha1CollisionDetector {
+            hasher: Sha1CD::configure()
+                .safe_hash(false)
+                .detect_collisions(true)
+                .build(),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    /// Returns the result for the data fed so far, leaving the hasher able to keep receiving data.
+    pub fn finalize(&self) -> Sha1Check {
+        let mut sha1 = Output::default();
+        let collision_detected = self
+            .hasher
+            .clone()
+            .finalize_into_dirty_cd(&mut sha1)
+            .is_err();
+        Sha1Check {
+            sha1: sha1.into(),
+            collision_detected,
+        }
+    }
+}
+
+/// Calculates the SHA-1 of `data` and checks it for collision attack blocks.
+pub fn detect_collision(data: &[u8]) -> Sha1Check {
+    let mut detector = Sha1CollisionDetector::new();
+    detector.update(data);
+    detector.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first 320 bytes of the two SHAttered PDFs: the common header and the two near-collision blocks,
+    /// after which both have the same SHA-1 state.
+    const SHATTERED_1: &[u8] = include_bytes!("../testdata/shattered-1-prefix.bin");
+    const SHATTERED_2: &[u8] = include_bytes!("../testdata/shattered-2-prefix.bin");
+
+    #[test]
+    fn ordinary_data_is_not_flagged() {
+        let check = detect_collision(b"abc");
+        assert_eq!(check.to_hex(), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert!(!check.collision_detected);
+    }
+
+    #[test]
+    fn shattered_blocks_are_flagged_with_the_standard_sha1() {
+        assert_ne!(SHATTERED_1, SHATTERED_2);
+        for data in [SHATTERED_1, SHATTERED_2] {
+            let check = detect_collision(data);
+            assert_eq!(check.to_hex(), "f92d74e3874587aaf443d1db961d4e26dde13e9c");
+            assert!(check.collision_detected);
+        }
+    }
+
+    #[test]
+    fn incremental_updates_match_one_shot() {
+        let mut detector = Sha1CollisionDetector::new();
+        for chunk in SHATTERED_1.chunks(37) {
+            detector.update(chunk);
+        }
+        assert_eq!(detector.finalize(), detect_collision(SHATTERED_1));
+        // finalize leaves the detector usable.
+        detector.update(b"trailer");
+        assert_eq!(
+            detector.finalize(),
+            detect_collision(&[SHATTERED_1, b"trailer"].concat())
+        );
+    }
+}
@@ -0,0 +1,455 @@
+//! RFC 3161 trusted timestamps, so a registration can carry third-party evidence of when a document existed
+//! rather than only the server's own clock.
+//!
+//! `build_request` produces the DER `TimeStampReq` to send to a timestamp authority (TSA), e.g. over HTTP
+//! with content type `application/timestamp-query`. The TSA's `TimeStampResp` is parsed with
+//! `TimestampToken::from_response_der`; the token inside it is what gets stored next to the registration
+//! and can be re-checked later with `TimestampToken::from_der` and `verify`.
+//!
+//! Verification checks that the token's message imprint is the document's digest, that its nonce is the one
+//! sent, and that the TSA's CMS signature is valid and its certificate chains to one of the caller's trust
+//! anchors, is valid at the current time (or a time the caller gives) and is a timestamping certificate (see
+//! `pkcs7`). The token's own `genTime` is reported but never used for the certificate check: the TSA writes it,
+//! so a TSA whose certificate has expired or whose key has leaked could backdate it into the old validity.
+
+use crate::pkcs7::{self, SignerVerification, TrustStore};
+use crate::{Digest, HasherError};
+use cmpv2::status::PkiStatus;
+use cms::signed_data::SignedData;
+use der::asn1::{Int, ObjectIdentifier, OctetString, Uint};
+use der::{Any, Decode, Encode};
+use rand_core::RngCore;
+use x509_cert::ext::pkix::name::GeneralName;
+use x509_cert::spki::AlgorithmIdentifier;
+use x509_tsp::{MessageImprint, TimeStampReq, TimeStampResp, TspVersion, TstInfo};
+
+const ID_SIGNED_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.2");
+const ID_CT_TST_INFO: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.16.1.4");
+const ID_KP_TIME_STAMPING: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.5.5.7.3.8");
+
+fn malformed(reason: impl std::fmt::Display) -> HasherError {
+    HasherError::InvalidDigest(format!("Malformed timestamp token: {}", reason))
+}
+
+/// A DER-encoded `TimeStampReq` and the nonce it carries, which the response must echo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimestampRequest {
+    pub der: Vec<u8>,
+    pub nonce: u64,
+}
+
+/// Builds a `TimeStampReq` for `digest`. `nonce` defaults to a random value; with `cert_req` the TSA is
+/// asked to embed its certificate in the token, which `verify` needs.
+pub fn build_request(
+    digest: &Digest,
+    nonce: Option<u64>,
+    cert_req: bool,
+) -> Result<TimestampRequest, HasherError> {
+    let oid = pkcs7::algorithm_oid(digest.algorithm()).ok_or_else(|| {
+        HasherError::UnsupportedAlgorithm(format!(
+            "{} has no OID and cannot be timestamped",
+            digest.algorithm()
+        ))
+    })?;
+    let nonce = nonce.unwrap_or_else(|| rand_core::OsRng.next_u64());
+    let encode_error = |e: der::Error| {
+        HasherError::InvalidInput(format!("Cannot encode timestamp request: {}", e))
+    };
+    let request = TimeStampReq {
+        version: TspVersion::V1,
+        message_imprint: MessageImprint {
+            hash_algorithm: AlgorithmIdentifier {
+                oid,
+                parameters: Some(Any::null()),
+            },
+            hashed_message: OctetString::new(digest.as_bytes()).map_err(encode_error)?,
+        },
+        req_policy: None,
+        nonce: Some(
+            Uint::new(&nonce.to_be_bytes())
+                .map_err(encode_error)?
+                .into(),
+        ),
+        cert_req,
+        extensions: None,
+    };
+    Ok(TimestampRequest {
+        der: request.to_der().map_err(encode_error)?,
+        nonce,
+    })
+}
+
+/// A timestamp token: a CMS `SignedData` whose content is the TSA's `TSTInfo`.
+#[derive(Debug, Clone)]
+pub struct TimestampToken {
+    der: Vec<u8>,
+    signed_data: SignedData,
+    tst_info: TstInfo,
+    tst_info_der: Vec<u8>,
+}
+
+impl TimestampToken {
+    /// Extracts the token from a DER `TimeStampResp`. Raises InvalidInput if the TSA did not grant the request.
+    pub fn from_response_der(der: &[u8]) -> Result<Self, HasherError> {
+        let response =
+            TimeStampResp::from_der(der).map_err(|e| malformed(format!("response: {}", e)))?;
+        if !matches!(
+            response.status.status,
+            PkiStatus::Accepted | PkiStatus::GrantedWithMods
+        ) {
+            let text = response
+                .status
+                .status_string
+                .iter()
+                .flat_map(|strings| strings.iter())
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .join("; ");
+            return Err(HasherError::InvalidInput(format!(
+                "Timestamp request was not granted: {:?} {}",
+                response.status.status, text
+            )));
+        }
+        let token = response
+            .time_stamp_token
+            .ok_or_else(|| malformed("the response carries no token"))?;
+        Self::from_der(&token.to_der().map_err(malformed)?)
+    }
+
+    /// Parses a DER timestamp token (a CMS `ContentInfo`). The signature is not checked; call `verify`.
+    pub fn from_der(der: &[u8]) -> Result<Self, HasherError> {
+        let content_info = cms::content_info::ContentInfo::from_der(der).map_err(malformed)?;
+        if content_info.content_type != ID_SIGNED_DATA {
+            return Err(malformed("the token is not CMS SignedData"));
+        }
+        let signed_data: SignedData = content_info.content.decode_as().map_err(malformed)?;
+        let encapsulated = &signed_data.encap_content_info;
+        if encapsulated.econtent_type != ID_CT_TST_INFO {
+            return Err(malformed("the signed content is not a TSTInfo"));
+        }
+        let content = encapsulated
+            .econtent
+            .as_ref()
+            .ok_or_else(|| malformed("the TSTInfo is missing"))?;
+        let tst_info_der = content
+            .decode_as::<OctetString>()
+            .map_err(malformed)?
+            .into_bytes();
+        let tst_info =
+            TstInfo::from_der(&tst_info_der).map_err(|e| malformed(format!("TSTInfo: {}", e)))?;
+        Ok(TimestampToken {
+            der: der.to_vec(),
+            signed_data,
+            tst_info,
+            tst_info_der,
+        })
+    }
+
+    pub fn to_der(&self) -> &[u8] {
+        &self.der
+    }
+
+    /// The time the TSA asserts, in Unix seconds.
+    pub fn gen_time(&self) -> u64 {
+        self.tst_info.gen_time.to_unix_duration().as_secs()
+    }
+
+    /// The token's serial number, hex-encoded.
+    pub fn serial_number(&self) -> String {
+        hex::encode(self.tst_info.serial_number.as_bytes())
+    }
+
+    /// The TSA policy OID, in dotted form.
+    pub fn policy(&self) -> String {
+        self.tst_info.policy.to_string()
+    }
+
+    /// The nonce echoed from the request, if any (and if it fits in 64 bits).
+    pub fn nonce(&self) -> Option<u64> {
+        self.tst_info.nonce.as_ref().and_then(int_to_u64)
+    }
+
+    /// The TSA's name from the token, if it gives one as a directory name.
+    pub fn tsa_name(&self) -> Option<String> {
+        match &self.tst_info.tsa {
+            Some(GeneralName::DirectoryName(name)) => Some(name.to_string()),
+            _ => None,
+        }
+    }
+
+    /// The digest the token timestamps.
+    pub fn message_imprint(&self) -> Result<Digest, HasherError> {
+        let imprint = &self.tst_info.message_imprint;
+        let algorithm = pkcs7::algorithm_from_oid(&imprint.hash_algorithm.oid)?;
+        let bytes = imprint.hashed_message.as_bytes();
+        if bytes.len() != algorithm.output_size() {
+            return Err(malformed(format!(
+                "{} imprint must be {} bytes, got {}",
+                algorithm,
+                algorithm.output_size(),
+                bytes.len()
+            )));
+        }
+        Ok(Digest::new(algorithm, bytes.to_vec()))
+    }
+
+    /// Checks the token against the document's `digest`, the `nonce` sent in the request (not checked if
+    /// `None`, e.g. when re-verifying a stored token) and the trusted roots in `trust`. The TSA's certificates
+    /// are checked at `validation_time` (Unix seconds), by default the current time.
+    pub fn verify(
+        &self,
+        digest: &Digest,
+        nonce: Option<u64>,
+        trust: &TrustStore,
+        validation_time: Option<u64>,
+    ) -> Result<TimestampVerification, HasherError> {
+        let imprint_matches = self
+            .message_imprint()
+            .is_ok_and(|imprint| imprint == *digest);
+        let nonce_matches = nonce.is_none_or(|nonce| self.nonce() == Some(nonce));
+        let signer = pkcs7::verify_signed_data(
+            &self.signed_data,
+            &self.tst_info_der,
+            trust,
+            validation_time,
+            Some(ID_KP_TIME_STAMPING),
+        )?;
+        Ok(TimestampVerification {
+            gen_time: self.gen_time(),
+            imprint_matches,
+            nonce_matches,
+            signer,
+        })
+    }
+}
+
+/// The outcome of checking a timestamp token. The token is only trustworthy if `is_valid()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimestampVerification {
+    /// The time the TSA asserts, in Unix seconds. Only as trustworthy as the TSA: it is not what the TSA's
+    /// certificate was checked at.
+    pub gen_time: u64,
+    pub imprint_matches: bool,
+    pub nonce_matches: bool,
+    /// The TSA's signature and certificate.
+    pub signer: SignerVerification,
+}
+
+impl TimestampVerification {
+    pub fn is_valid(&self) -> bool {
+        self.imprint_matches && self.nonce_matches && self.signer.is_valid()
+    }
+}
+
+/// Converts a non-negative INTEGER of at most 64 bits.
+fn int_to_u64(value: &Int) -> Option<u64> {
+    let bytes = value.as_bytes();
+    if bytes.first().is_some_and(|b| b & 0x80 != 0) {
+        return None;
+    }
+    let bytes = &bytes[bytes.iter().take_while(|&&b| b == 0).count()..];
+    let mut buffer = [0u8; 8];
+    buffer
+        .get_mut(8usize.checked_sub(bytes.len())?..)?
+        .copy_from_slice(bytes);
+    Some(u64::from_be_bytes(buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Algorithm;
+
+    /// A response from an OpenSSL test TSA for the SHA256 of `DOCUMENT`, with nonce `NONCE`.
+    const RESPONSE: &[u8] = include_bytes!("../testdata/timestamp-response.der");
+    /// The same request answered by a TSA whose certificate expired two minutes later.
+    const RESPONSE_EXPIRED_TSA: &[u8] =
+        include_bytes!("../testdata/timestamp-response-expired-tsa.der");
+    /// A response rejecting a request for a policy the TSA does not offer.
+    const REJECTED: &[u8] = include_bytes!("../testdata/timestamp-rejected.der");
+    const ROOT: &str = include_str!("../testdata/root.pem");
+    const OTHER_ROOT: &str = include_str!("../testdata/other.pem");
+    const DOCUMENT: &[u8] = b"hashsure timestamp test";
+    const NONCE: u64 = 0x0123_4567_89ab_cdef;
+
+    fn token() -> TimestampToken {
+        TimestampToken::from_response_der(RESPONSE).unwrap()
+    }
+
+    fn trust() -> TrustStore {
+        TrustStore::from_pem(ROOT).unwrap()
+    }
+
+    #[test]
+    fn granted_response_verifies() {
+        let token = token();
+        assert_eq!(token.gen_time(), 1_792_124_982);
+        assert_eq!(token.serial_number(), "02");
+        assert_eq!(token.policy(), "1.2.3.4.1");
+        assert_eq!(token.nonce(), Some(NONCE));
+        assert_eq!(
+            token.tsa_name().as_deref(),
+            Some("CN=Hashsure Test TSA,O=Hashsure")
+        );
+        assert_eq!(
+            token.message_imprint().unwrap(),
+            Algorithm::Sha256.digest(DOCUMENT)
+        );
+
+        let verification = token
+            .verify(
+                &Algorithm::Sha256.digest(DOCUMENT),
+                Some(NONCE),
+                &trust(),
+                None,
+            )
+            .unwrap();
+        assert!(verification.is_valid(), "{:?}", verification);
+        assert_eq!(
+            verification.signer.subject,
+            "CN=Hashsure Test TSA,O=Hashsure"
+        );
+    }
+
+    #[test]
+    fn stored_token_reverifies() {
+        let token = TimestampToken::from_der(token().to_der()).unwrap();
+        assert!(
+            token
+                .verify(&Algorithm::Sha256.digest(DOCUMENT), None, &trust(), None)
+                .unwrap()
+                .is_valid()
+        );
+    }
+
+    #[test]
+    fn other_document_does_not_match_imprint() {
+        let verification = token()
+            .verify(
+                &Algorithm::Sha256.digest(b"another document"),
+                Some(NONCE),
+                &trust(),
+                None,
+            )
+            .unwrap();
+        assert!(!verification.imprint_matches);
+        assert!(verification.nonce_matches && verification.signer.is_valid());
+        assert!(!verification.is_valid());
+
+        let other_algorithm = Algorithm::Sha3_256.digest(DOCUMENT);
+        assert!(
+            !token()
+                .verify(&other_algorithm, Some(NONCE), &trust(), None)
+                .unwrap()
+                .imprint_matches
+        );
+    }
+
+    #[test]
+    fn other_nonce_does_not_match() {
+        let verification = token()
+            .verify(
+                &Algorithm::Sha256.digest(DOCUMENT),
+                Some(NONCE + 1),
+                &trust(),
+                None,
+            )
+            .unwrap();
+        assert!(!verification.nonce_matches);
+        assert!(verification.imprint_matches && verification.signer.is_valid());
+        assert!(!verification.is_valid());
+    }
+
+    #[test]
+    fn untrusted_tsa_is_not_valid() {
+        let other = TrustStore::from_pem(OTHER_ROOT).unwrap();
+        let verification = token()
+            .verify(
+                &Algorithm::Sha256.digest(DOCUMENT),
+                Some(NONCE),
+                &other,
+                None,
+            )
+            .unwrap();
+        assert!(verification.signer.signature_valid);
+        assert!(!verification.signer.certificate_trusted);
+        assert!(!verification.is_valid());
+    }
+
+    #[test]
+    fn tsa_certificate_is_checked_at_the_current_time_not_gen_time() {
+        let token = TimestampToken::from_response_der(RESPONSE_EXPIRED_TSA).unwrap();
+        let digest = Algorithm::Sha256.digest(DOCUMENT);
+        let verification = token.verify(&digest, Some(NONCE), &trust(), None).unwrap();
+        assert_eq!(verification.gen_time, token.gen_time());
+        assert!(
+            verification.imprint_matches
+                && verification.nonce_matches
+                && verification.signer.signature_valid
+        );
+        assert!(
+            !verification.signer.certificate_trusted,
+            "the TSA certificate has expired"
+        );
+        assert!(!verification.is_valid());
+
+        let then = token
+            .verify(&digest, Some(NONCE), &trust(), Some(token.gen_time()))
+            .unwrap();
+        assert!(then.is_valid(), "{:?}", then);
+    }
+
+    #[test]
+    fn altered_token_is_not_valid() {
+        // Change the imprint inside the signed TSTInfo to the digest of another document.
+        let digest = Algorithm::Sha256.digest(DOCUMENT);
+        let forged_digest = Algorithm::Sha256.digest(b"another document");
+        let der = token().to_der().to_vec();
+        let at = der
+            .windows(32)
+            .position(|window| window == digest.as_bytes())
+            .unwrap();
+        let mut forged = der.clone();
+        forged[at..at + 32].copy_from_slice(forged_digest.as_bytes());
+
+        let verification = TimestampToken::from_der(&forged)
+            .unwrap()
+            .verify(&forged_digest, None, &trust(), None)
+            .unwrap();
+        assert!(verification.imprint_matches);
+        assert!(!verification.signer.message_digest_valid);
+        assert!(!verification.is_valid());
+    }
+
+    #[test]
+    fn rejected_response_is_an_error() {
+        let error = TimestampToken::from_response_der(REJECTED).unwrap_err();
+        assert!(
+            matches!(error, HasherError::InvalidInput(ref message) if message.contains("not granted")),
+            "{:?}",
+            error
+        );
+        assert!(matches!(
+            TimestampToken::from_response_der(&RESPONSE[1..]),
+            Err(HasherError::InvalidDigest(_))
+        ));
+    }
+
+    #[test]
+    fn request_carries_digest_and_nonce() {
+        let digest = Algorithm::Sha256.digest(DOCUMENT);
+        let request = build_request(&digest, Some(NONCE), true).unwrap();
+        assert_eq!(request.nonce, NONCE);
+        let decoded = TimeStampReq::from_der(&request.der).unwrap();
+        assert_eq!(
+            decoded.message_imprint.hashed_message.as_bytes(),
+            digest.as_bytes()
+        );
+        assert_eq!(decoded.nonce.as_ref().and_then(int_to_u64), Some(NONCE));
+        assert!(decoded.cert_req);
+        assert!(matches!(
+            build_request(&Algorithm::Blake3.digest(DOCUMENT), None, true),
+            Err(HasherError::UnsupportedAlgorithm(_))
+        ));
+    }
+}
@@ -0,0 +1,312 @@
+//! An append-only Merkle log of registrations in the style of RFC 6962 / RFC 9162 (Certificate
+//! Transparency), so that deleting, reordering or back-dating a registration is detectable.
+//!
+//! Each entry is a leaf of the tree in `merkle`. The log operator periodically publishes a signed tree head
+//! (STH): the tree size, the root hash and a timestamp, signed with Ed25519. Anyone holding two STHs can ask
+//! for a consistency proof that the later tree extends the earlier one, and anyone holding an entry can ask
+//! for an inclusion proof against a published root.
+//!
+//! The STH signature covers the label `hashsure-tree-head-v1`, a zero byte, then the tree size and the
+//! timestamp (8 big-endian bytes each), the root hash and the UTF-8 key id, each preceded by its length
+//! as 4 big-endian bytes.
+
+use crate::HasherError;
+use crate::ed25519::{Ed25519PrivateKey, Ed25519PublicKey, SIGNATURE_LENGTH};
+use crate::encoding::push_field;
+use crate::merkle::{self, Hash};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use serde_json::Value;
+
+/// The tree head format version written to and required in the JSON.
+pub const TREE_HEAD_VERSION: u64 = 1;
+
+const SIGNING_LABEL: &[u8] = b"hashsure-tree-head-v1\0";
+
+/// The leaves of the log, in append order. Entries themselves are not kept, only their leaf hashes.
+#[derive(Debug, Clone, Default)]
+pub struct TransparencyLog {
+    leaves: Vec<Hash>,
+}
+
+impl TransparencyLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restores a log from its stored leaf hashes, in append order.
+    pub fn from_leaf_hashes(leaves: Vec<Hash>) -> Self {
+        TransparencyLog { leaves }
+    }
+
+    /// Appends an entry and returns its index.
+    pub fn append(&mut self, entry: &[u8]) -> usize {
+        self.leaves.push(merkle::leaf_hash(entry));
+        self.leaves.len() - 1
+    }
+
+    pub fn size(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn leaves(&self) -> &[Hash] {
+        &self.leaves
+    }
+
+    /// The root of the tree over the first `tree_size` entries, or `None` if the log is smaller.
+    pub fn root_at(&self, tree_size: usize) -> Option<Hash> {
+        self.leaves.get(..tree_size).map(merkle::root)
+    }
+
+    /// The audit path for the entry at `index` in the tree of the first `tree_size` entries.
+    pub fn inclusion_proof(&self, index: usize, tree_size: usize) -> Option<Vec<Hash>> {
+        merkle::inclusion_proof(self.leaves.get(..tree_size)?, index)
+    }
+
+    /// The proof that the tree of the first `new_size` entries extends the tree of the first `old_size`.
+    pub fn consistency_proof(&self, old_size: usize, new_size: usize) -> Option<Vec<Hash>> {
+        merkle::consistency_proof(self.leaves.get(..new_size)?, old_size)
+    }
+
+    /// Signs the current tree head. `key_id` defaults to the public key's `key_id()`.
+    pub fn sign_tree_head(
+        &self,
+        private_key: &Ed25519PrivateKey,
+        key_id: Option<&str>,
+        timestamp: u64,
+    ) -> SignedTreeHead {
+        let key_id = key_id.map_or_else(|| private_key.public_key().key_id(), str::to_string);
+        let tree_size = self.leaves.len() as u64;
+        let root_hash = merkle::root(&self.leaves);
+        let signature = private_key.sign(&signing_input(tree_size, timestamp, &root_hash, &key_id));
+        SignedTreeHead {
+            tree_size,
+            timestamp,
+            root_hash,
+            key_id,
+            signature,
+        }
+    }
+}
+
+/// A signed tree head: a commitment by the log operator to the log's contents at a point in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedTreeHead {
+    pub tree_size: u64,
+    pub timestamp: u64,
+    pub root_hash: Hash,
+    pub key_id: String,
+    signature: [u8; SIGNATURE_LENGTH],
+}
+
+fn signing_input(tree_size: u64, timestamp: u64, root_hash: &Hash, key_id: &str) -> Vec<u8> {
+    let mut input = SIGNING_LABEL.to_vec();
+    push_field(&mut input, &tree_size.to_be_bytes());
+    push_field(&mut input, &timestamp.to_be_bytes());
+    push_field(&mut input, root_hash);
+    push_field(&mut input, key_id.as_bytes());
+    input
+}
+
+fn malformed(reason: impl std::fmt::Display) -> HasherError {
+    HasherError::InvalidDigest(format!("Malformed tree head: {}", reason))
+}
+
+impl SignedTreeHead {
+    pub fn signature(&self) -> &[u8] {
+        &self.signature
+    }
+
+    /// Checks the tree head's signature with `public_key`.
+    pub fn verify(&self, public_key: &Ed25519PublicKey) -> bool {
+        let input = signing_input(
+            self.tree_size,
+            self.timestamp,
+            &self.root_hash,
+            &self.key_id,
+        );
+        public_key.verify(&input, &self.signature).unwrap_or(false)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "version": TREE_HEAD_VERSION,
+            "tree_size": self.tree_size,
+            "timestamp": self.timestamp,
+            "root_hash": hex::encode(self.root_hash),
+            "key_id": self.key_id,
+            "signature": BASE64URL.encode(self.signature),
+        })
+        .to_string()
+    }
+
+    /// Parses a tree head from its JSON form. The signature is not checked; call `verify`.
+    pub fn from_json(json: &str) -> Result<Self, HasherError> {
+        let value: Value = serde_json::from_str(json).map_err(malformed)?;
+        let field = |name: &str| {
+            value
+                .get(name)
+                .ok_or_else(|| malformed(format!("missing '{}'", name)))
+        };
+        let string = |name: &str| {
+            field(name)?
+                .as_str()
+                .ok_or_else(|| malformed(format!("'{}' is not a string", name)))
+        };
+        let integer = |name: &str| {
+            field(name)?
+                .as_u64()
+                .ok_or_else(|| malformed(format!("'{}' is not an integer", name)))
+        };
+
+        let version = integer("version")?;
+        if version != TREE_HEAD_VERSION {
+            return Err(malformed(format!("unsupported version {}", version)));
+        }
+        let root_hash = hex::decode(string("root_hash")?).map_err(malformed)?;
+        let root_hash = root_hash.try_into().map_err(|h: Vec<u8>| {
+            malformed(format!("root hash must be 32 bytes, got {}", h.len()))
+        })?;
+        let signature = BASE64URL.decode(string("signature")?).map_err(malformed)?;
+        let signature = signature.try_into().map_err(|s: Vec<u8>| {
+            malformed(format!(
+                "signature must be {} bytes, got {}",
+                SIGNATURE_LENGTH,
+                s.len()
+            ))
+        })?;
+        Ok(SignedTreeHead {
+            tree_size: integer("tree_size")?,
+            timestamp: integer("timestamp")?,
+            root_hash,
+            key_id: string("key_id")?.to_string(),
+            signature,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn private_key() -> Ed25519PrivateKey {
+        Ed25519PrivateKey::from_raw(&[7; 32]).unwrap()
+    }
+
+    fn log(entries: usize) -> TransparencyLog {
+        let mut log = TransparencyLog::new();
+        for i in 0..entries {
+            assert_eq!(log.append(format!("registration {}", i).as_bytes()), i);
+        }
+        log
+    }
+
+    #[test]
+    fn tree_head_round_trips_and_verifies() {
+        let log = log(5);
+        let sth = log.sign_tree_head(&private_key(), None, 1_700_000_000);
+        assert_eq!(sth.tree_size, 5);
+        assert_eq!(sth.root_hash, log.root_at(5).unwrap());
+        assert_eq!(sth.key_id, private_key().public_key().key_id());
+        let parsed = SignedTreeHead::from_json(&sth.to_json()).unwrap();
+        assert_eq!(parsed, sth);
+        assert!(parsed.verify(&private_key().public_key()));
+        let other = Ed25519PrivateKey::from_raw(&[8; 32]).unwrap();
+        assert!(!parsed.verify(&other.public_key()));
+        let empty = TransparencyLog::new().sign_tree_head(&private_key(), Some("log-1"), 0);
+        assert_eq!(empty.root_hash, merkle::root(&[]));
+        assert!(empty.verify(&private_key().public_key()));
+    }
+
+    #[test]
+    fn modified_tree_head_does_not_verify() {
+        let public_key = private_key().public_key();
+        let sth = log(5).sign_tree_head(&private_key(), None, 1_700_000_000);
+        let modified = [
+            SignedTreeHead {
+                tree_size: 4,
+                ..sth.clone()
+            },
+            SignedTreeHead {
+                root_hash: log(4).root_at(4).unwrap(),
+                ..sth.clone()
+            },
+            SignedTreeHead {
+                timestamp: 1_700_000_001,
+                ..sth.clone()
+            },
+            SignedTreeHead {
+                key_id: "other".to_string(),
+                ..sth.clone()
+            },
+        ];
+        for sth in modified {
+            let parsed = SignedTreeHead::from_json(&sth.to_json()).unwrap();
+            assert!(!parsed.verify(&public_key), "{:?}", parsed);
+        }
+        let mut json: Value = serde_json::from_str(&sth.to_json()).unwrap();
+        json["root_hash"] = "00".into();
+        assert!(SignedTreeHead::from_json(&json.to_string()).is_err());
+    }
+
+    #[test]
+    fn inclusion_proofs_verify_against_current_and_earlier_roots() {
+        let log = log(7);
+        for tree_size in 1..=7 {
+            let root = log.root_at(tree_size).unwrap();
+            for index in 0..tree_size {
+                let proof = log.inclusion_proof(index, tree_size).unwrap();
+                let leaf = &log.leaves()[index];
+                assert!(merkle::verify_inclusion(
+                    leaf, index, tree_size, &proof, &root
+                ));
+                let other_leaf = merkle::leaf_hash(b"never registered");
+                assert!(!merkle::verify_inclusion(
+                    &other_leaf,
+                    index,
+                    tree_size,
+                    &proof,
+                    &root
+                ));
+            }
+            assert_eq!(log.inclusion_proof(tree_size, tree_size), None);
+        }
+        assert_eq!(log.inclusion_proof(0, 8), None);
+        assert_eq!(log.root_at(8), None);
+    }
+
+    #[test]
+    fn consistency_proofs_show_the_log_only_grew() {
+        let mut grown = log(3);
+        let old = grown.sign_tree_head(&private_key(), None, 1_700_000_000);
+        for i in 3..7 {
+            grown.append(format!("registration {}", i).as_bytes());
+        }
+        let new = grown.sign_tree_head(&private_key(), None, 1_700_000_100);
+        let (old_size, new_size) = (old.tree_size as usize, new.tree_size as usize);
+        let proof = grown.consistency_proof(old_size, new_size).unwrap();
+        assert!(merkle::verify_consistency(
+            old_size,
+            new_size,
+            &proof,
+            &old.root_hash,
+            &new.root_hash
+        ));
+
+        // A log that rewrote an old entry can't prove it extends the old tree head.
+        let mut leaves = grown.leaves().to_vec();
+        leaves[1] = merkle::leaf_hash(b"rewritten");
+        let forked = TransparencyLog::from_leaf_hashes(leaves);
+        let forked_root = forked.root_at(new_size).unwrap();
+        let proof = forked.consistency_proof(old_size, new_size).unwrap();
+        assert!(!merkle::verify_consistency(
+            old_size,
+            new_size,
+            &proof,
+            &old.root_hash,
+            &forked_root
+        ));
+        assert_eq!(grown.consistency_proof(0, new_size), None);
+        assert_eq!(grown.consistency_proof(3, 8), None);
+    }
+}
@@ -2,7 +2,7 @@ import os
 from datetime import datetime
 from fastapi import FastAPI, UploadFile, File, HTTPException, Depends
 from pydantic import BaseModel
-from sqlalchemy import create_engine, Column, Integer, String, DateTime
+from sqlalchemy import create_engine, text, Column, Integer, String, DateTime
 from sqlalchemy.orm import sessionmaker, declarative_base, Session
 from sqlalchemy.exc import IntegrityError
 import document_hasher_rust # Our Rust module!
@@ -38,10 +38,15 @@ read_secret_result = client.secrets.kv.v2.read_secret_version(
 DATABASE_URL = read_secret_result['data']['data']['DATABASE_URL']
 
 # IMPORTANT: For production, generate a strong, random key and store it securely (e.g., in a secrets manager)
-HMAC_SECRET_KEY = read_secret_result['data']['data']['HMAC_SECRET_KEY'].encode('utf-8')
+# HmacKey refuses to start the server with a key shorter than 32 bytes (the SHA256 output size)
+# or one made of a few repeated characters
+HMAC_SECRET_KEY = document_hasher_rust.HmacKey(
+    read_secret_result['data']['data']['HMAC_SECRET_KEY'].encode('utf-8'),
+    min_entropy_bits=64
+)
 
-if len(HMAC_SECRET_KEY) < 32: # HMAC keys should ideally be as long as the hash output (32 bytes for SHA256)
-    print("WARNING: HMAC_SECRET_KEY is too short. Please use a key of at least 32 bytes for production.")
+# Uploads are hashed incrementally in chunks of this size to keep memory use constant
+UPLOAD_CHUNK_SIZE = 1024 * 1024
 
 # SQLAlchemy setup
 Engine = create_engine(DATABASE_URL)
@@ -56,6 +61,9 @@ class DocumentHash(Base):
     sha256_hash = Column(String(64), unique=True, nullable=False, index=True) # Plain SHA256
     hmac_tag = Column(String(64), nullable=False) # HMAC-SHA256 tag for authenticity of the record
     uploaded_at = Column(DateTime, default=datetime.utcnow)
+    # Which record encoding hmac_tag covers: 0 for legacy tags over the hex SHA256 alone,
+    # otherwise the canonical record version (document_hasher_rust.RECORD_VERSION)
+    record_version = Column(Integer, nullable=False, server_default="0")
 
     def __repr__(self):
         return f"<DocumentHash {self.file_name}: {self.sha256_hash[:10]}... (HMAC: {self.hmac_tag[:10]}...)>"
@@ -89,6 +97,11 @@ def get_db():
 @app.on_event("startup")
 def on_startup():
     Base.metadata.create_all(bind=Engine)
+    # Tables created before records were versioned lack the column; their rows all carry legacy tags
+    with Engine.begin() as connection:
+        connection.execute(text(
+            "ALTER TABLE document_hashes ADD COLUMN IF NOT EXISTS record_version INTEGER NOT NULL DEFAULT 0"
+        ))
     print("Database tables created (if they didn't exist).")
 
 # --- API Endpoints ---
@@ -102,15 +115,22 @@ async def upload_document(pdf: UploadFile = File(...), db: Session = Depends(get
     if not pdf.filename:
         raise HTTPException(status_code=400, detail="No selected file.")
 
-    file_content = await pdf.read()
-    
-    # 1. Calculate plain SHA256 using Rust
-    sha256 = document_hasher_rust.calculate_sha256_bytes(file_content)
+    # 1. Calculate plain SHA256 using Rust, feeding the upload in chunks
+    # The same chunks go through SHA-1 collision detection, so crafted colliding documents are rejected
+    hasher = document_hasher_rust.Sha256Hasher()
+    sha1_detector = document_hasher_rust.Sha1CollisionDetector()
+    while chunk := await pdf.read(UPLOAD_CHUNK_SIZE):
+        hasher.update(chunk)
+        sha1_detector.update(chunk)
+    if sha1_detector.collision_detected():
+        raise HTTPException(status_code=400, detail="File contains SHA-1 collision attack blocks.")
+    sha256 = hasher.hexdigest()
     file_name = pdf.filename
 
-    # 2. Calculate HMAC-SHA256 using Rust (message is the plain SHA256 hash)
-    # The HMAC_tag authenticates that *this specific plain hash* was registered by *this server*
-    hmac_tag = document_hasher_rust.calculate_hmac_sha256(HMAC_SECRET_KEY, sha256.encode('utf-8'))
+    # 2. Calculate HMAC-SHA256 using Rust over the whole record (hash, file name and upload time)
+    # The HMAC_tag authenticates that *this record* was registered by *this server*, so it cannot be renamed or re-dated
+    uploaded_at = datetime.utcnow()
+    hmac_tag = document_hasher_rust.calculate_record_hmac(HMAC_SECRET_KEY, sha256, file_name, uploaded_at)
 
 
     # Check if plain SHA256 hash already exists to prevent duplicate records for the same file
@@ -130,7 +150,9 @@ async def upload_document(pdf: UploadFile = File(...), db: Session = Depends(get
     new_doc_hash = DocumentHash(
         file_name=file_name,
         sha256_hash=sha256,
-        hmac_tag=hmac_tag
+        hmac_tag=hmac_tag,
+        uploaded_at=uploaded_at,
+        record_version=document_hasher_rust.RECORD_VERSION
     )
     try:
         db.add(new_doc_hash)
@@ -163,13 +185,28 @@ async def verify_document(request: VerificationRequest, db: Session = Depends(ge
     doc_hash_record = db.query(DocumentHash).filter_by(sha256_hash=client_sha256_hash).first()
 
     if doc_hash_record:
-        # Recalculate the HMAC-SHA256 based on the stored plain hash and the server's secret key
-        recalculated_hmac_tag = document_hasher_rust.calculate_hmac_sha256(
-            HMAC_SECRET_KEY,
-            doc_hash_record.sha256_hash.encode('utf-8')
-        )
-
-        if recalculated_hmac_tag == doc_hash_record.hmac_tag:
+        # Check the stored HMAC tag against every field of the stored record in constant time.
+        # Legacy rows only authenticate the hash; they are not re-tagged, since that would vouch for
+        # a file name and upload time the old tag never covered
+        try:
+            if doc_hash_record.record_version == 0:
+                hmac_valid = document_hasher_rust.verify_hmac_sha256(
+                    HMAC_SECRET_KEY,
+                    doc_hash_record.sha256_hash.encode('utf-8'),
+                    doc_hash_record.hmac_tag
+                )
+            else:
+                hmac_valid = document_hasher_rust.verify_record_hmac(
+                    HMAC_SECRET_KEY,
+                    doc_hash_record.sha256_hash,
+                    doc_hash_record.file_name,
+                    doc_hash_record.uploaded_at,
+                    doc_hash_record.hmac_tag
+                )
+        except document_hasher_rust.InvalidDigestError:
+            hmac_valid = False
+
+        if hmac_valid:
             return DocumentVerificationResponse(
                 message="Document verified successfully. Integrity and record authenticity confirmed.",
                 is_original=True,